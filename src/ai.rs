@@ -0,0 +1,349 @@
+// Enemy behaviour.
+//
+// Each enemy has a brain with a simple state machine:
+// - Patrol: wander between random tiles near where it spawned, until it sees
+//   a player.
+// - Chase: head for the nearest player by the flow fields, until close enough
+//   to shoot, or until it's gone a while without seeing anyone.
+// - Attack: stand still and shoot at the nearest player it can see.
+// - Flee: run away from players once badly hurt, until it's out of sight for
+//   a while.
+//
+// Enemies only see players within their sight range, and not through walls.
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use crate::dimensions::{WorldCoord as Coord, WorldPoint as Point};
+use crate::flow_field::FlowFields;
+use crate::game::{Player, Unit, UnitAnim, TICKS_PER_SEC, UID};
+use crate::map::{Map, TilePoint, ToTilePoint};
+use crate::pathfinding::find_path;
+use crate::sprite_sheet::SpriteKey;
+
+// How long an enemy keeps chasing after losing sight of its target, and how
+// long a fleeing enemy has to be out of sight before it calms down.
+const LOSE_INTEREST_TICKS: u32 = TICKS_PER_SEC * 3;
+// How far from home, in tiles, patrols wander.
+const PATROL_RADIUS: i64 = 4;
+// How long an enemy waits between patrol legs.
+const PATROL_WAIT_TICKS: u32 = TICKS_PER_SEC;
+// How long an enemy tries to finish a patrol leg before picking another, in
+// case it's been pushed off its path.
+const PATROL_GIVE_UP_TICKS: u32 = TICKS_PER_SEC * 5;
+// How close to the center of a tile on its path an enemy has to get before
+// heading for the next one.
+const WAYPOINT_RAD: Coord = Coord(8.0);
+
+// What a kind of enemy is like. Spawners on the map name one of these.
+pub struct EnemyKind {
+  pub name: &'static str,
+  pub sprite: &'static str,
+  pub rad: Coord,
+  pub speed: Coord,
+  pub health: u32,
+  pub ticks_per_shot: u32,
+  pub shot_damage: u32,
+  // How far away it can see players.
+  pub sight_range: Coord,
+  // How close it gets to a player before it stops to shoot.
+  pub attack_range: Coord,
+  // It flees once its health drops to this or below.
+  pub flee_health: u32,
+}
+
+const ENEMY_KINDS: [EnemyKind; 1] = [EnemyKind {
+  name: "grunt",
+  sprite: "grunt",
+  rad: Coord(10.0),
+  speed: Coord(150.0),
+  health: 50,
+  ticks_per_shot: TICKS_PER_SEC,
+  shot_damage: 10,
+  sight_range: Coord(400.0),
+  attack_range: Coord(250.0),
+  flee_health: 10,
+}];
+
+pub fn enemy_kind(name: &str) -> Result<&'static EnemyKind, String> {
+  ENEMY_KINDS
+    .iter()
+    .find(|kind| kind.name == name)
+    .ok_or_else(|| format!("unknown enemy kind \"{}\"", name))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Behavior {
+  Patrol,
+  Chase,
+  Attack,
+  Flee,
+}
+
+pub struct Brain {
+  pub kind: &'static EnemyKind,
+  pub behavior: Behavior,
+  // The tile of the spawner it came from, which it patrols around.
+  pub home: TilePoint,
+
+  // Ticks since it last saw a player.
+  ticks_unseen: u32,
+  // The tiles left to walk on the current patrol leg.
+  path: Vec<TilePoint>,
+  // Ticks spent on the current patrol leg, or waiting before the next.
+  patrol_ticks: u32,
+}
+
+// A new enemy of the given kind standing on the tile home.
+pub fn spawn_enemy(kind: &'static EnemyKind, uid: UID, home: TilePoint) -> Unit {
+  let pos = home.center_to_world_point();
+  Unit {
+    uid,
+    sprite_key: SpriteKey::new(kind.sprite),
+
+    pos,
+    prev_pos: pos,
+    heading: Point::new(Coord(1.0), Coord(0.0)),
+    move_dir: Point::new(Coord(0.0), Coord(0.0)),
+    rad: kind.rad,
+    base_speed: kind.speed,
+
+    shooting: false,
+    ticks_per_shot: kind.ticks_per_shot,
+    ticks_to_shot: kind.ticks_per_shot,
+    shot_damage: kind.shot_damage,
+
+    health: kind.health,
+    max_health: kind.health,
+
+    anim: UnitAnim::new(),
+    brain: Some(Brain {
+      kind,
+      behavior: Behavior::Patrol,
+      home,
+
+      ticks_unseen: 0,
+      path: vec![],
+      patrol_ticks: 0,
+    }),
+  }
+}
+
+// Decides what an enemy does this tick: which way it moves, where it aims and
+// whether it shoots. Units without a brain are left alone.
+pub fn think(
+  unit: &mut Unit,
+  map: &Map,
+  players: &[Player],
+  flow_fields: &FlowFields,
+  rng: &mut StdRng,
+) {
+  let mut brain = match unit.brain.take() {
+    Some(brain) => brain,
+    None => return,
+  };
+  if unit.is_alive() {
+    brain.think(unit, map, players, flow_fields, rng);
+  }
+  unit.brain = Some(brain);
+}
+
+impl Brain {
+  fn think(
+    &mut self,
+    unit: &mut Unit,
+    map: &Map,
+    players: &[Player],
+    flow_fields: &FlowFields,
+    rng: &mut StdRng,
+  ) {
+    let target = self.visible_target(unit, map, players);
+    if target.is_some() {
+      self.ticks_unseen = 0;
+    } else {
+      self.ticks_unseen = self.ticks_unseen.saturating_add(1);
+    }
+    self.behavior = self.next_behavior(unit, target);
+
+    unit.move_dir = Point::new(Coord(0.), Coord(0.));
+    unit.shooting = false;
+    match self.behavior {
+      Behavior::Patrol => self.patrol(unit, map, rng),
+      Behavior::Chase => {
+        let dir = flow_fields.direction(map, unit.pos);
+        let dir = dir.or_else(|| target.map(|target| target - unit.pos));
+        if let Some(dir) = dir {
+          unit.move_dir = dir;
+        }
+        match target {
+          Some(target) => unit.aim_at(target),
+          None => face(unit, unit.move_dir),
+        }
+      }
+      Behavior::Attack => {
+        if let Some(target) = target {
+          unit.aim_at(target);
+          unit.shooting = true;
+        }
+      }
+      Behavior::Flee => {
+        if let Some(target) = target {
+          unit.move_dir = unit.pos - target;
+        }
+        face(unit, unit.move_dir);
+      }
+    }
+  }
+
+  fn next_behavior(&self, unit: &Unit, target: Option<Point>) -> Behavior {
+    let lost_interest = self.ticks_unseen > LOSE_INTEREST_TICKS;
+    if unit.health <= self.kind.flee_health {
+      return match (self.behavior, target) {
+        (_, Some(_)) => Behavior::Flee,
+        (Behavior::Flee, None) if !lost_interest => Behavior::Flee,
+        _ => Behavior::Patrol,
+      };
+    }
+    match target {
+      Some(target) if (target - unit.pos).magnitude() <= self.kind.attack_range => Behavior::Attack,
+      Some(_) => Behavior::Chase,
+      None if self.behavior == Behavior::Patrol || lost_interest => Behavior::Patrol,
+      // Keep after them for a while, by the flow fields.
+      None => Behavior::Chase,
+    }
+  }
+
+  // The position of the nearest living player the unit can see, if any.
+  fn visible_target(&self, unit: &Unit, map: &Map, players: &[Player]) -> Option<Point> {
+    players
+      .iter()
+      .map(|p| &p.unit)
+      .filter(|p| p.is_alive())
+      .map(|p| (p.pos, (p.pos - unit.pos).magnitude()))
+      .filter(|(pos, dist)| *dist <= self.kind.sight_range && map.line_of_sight(unit.pos, *pos))
+      .min_by(|(_, a), (_, b)| a.0.total_cmp(&b.0))
+      .map(|(pos, _)| pos)
+  }
+
+  // Walks the current patrol leg, or waits and then picks the next one.
+  fn patrol(&mut self, unit: &mut Unit, map: &Map, rng: &mut StdRng) {
+    self.patrol_ticks += 1;
+    if self.path.is_empty() || self.patrol_ticks > PATROL_GIVE_UP_TICKS {
+      self.path.clear();
+      if self.patrol_ticks < PATROL_WAIT_TICKS {
+        return;
+      }
+      self.patrol_ticks = 0;
+      let x = self.home.x() as i64 + rng.gen_range(-PATROL_RADIUS..=PATROL_RADIUS);
+      let y = self.home.y() as i64 + rng.gen_range(-PATROL_RADIUS..=PATROL_RADIUS);
+      let goal = TilePoint::new(
+        x.clamp(0, map.width as i64 - 1) as u32,
+        y.clamp(0, map.height as i64 - 1) as u32,
+      );
+      if let Some(path) = find_path(map, unit.pos.to_tile_point(), goal) {
+        self.path = path;
+      }
+      return;
+    }
+
+    let to_next = self.path[0].center_to_world_point() - unit.pos;
+    if to_next.magnitude() <= WAYPOINT_RAD {
+      self.path.remove(0);
+      if self.path.is_empty() {
+        // Start waiting.
+        self.patrol_ticks = 0;
+      }
+      return;
+    }
+    unit.move_dir = to_next;
+    face(unit, to_next);
+  }
+}
+
+// Turns unit to face along dir, if it's not zero.
+fn face(unit: &mut Unit, dir: Point) {
+  unit.aim_at(unit.pos + dir);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::test_map;
+  use rand::SeedableRng;
+
+  fn grunt_at(x: u32, y: u32) -> Unit {
+    spawn_enemy(enemy_kind("grunt").unwrap(), 100, TilePoint::new(x, y))
+  }
+
+  fn player_at(x: u32, y: u32) -> Player {
+    Player::new(0, TilePoint::new(x, y).center_to_world_point())
+  }
+
+  // Has unit think once, with the players where given.
+  fn think_once(unit: &mut Unit, map: &Map, players: &[Player]) -> Behavior {
+    let mut flow_fields = FlowFields::new();
+    flow_fields.update(map, players);
+    let mut rng = StdRng::seed_from_u64(0);
+    think(unit, map, players, &flow_fields, &mut rng);
+    unit.brain.as_ref().unwrap().behavior
+  }
+
+  #[test]
+  fn unknown_kinds_are_errors() {
+    assert!(enemy_kind("grunt").is_ok());
+    assert_eq!(
+      enemy_kind("dragon").err().unwrap(),
+      "unknown enemy kind \"dragon\""
+    );
+  }
+
+  #[test]
+  fn patrols_when_nobody_is_in_sight() {
+    let map = test_map();
+    // Too far away to see.
+    let mut unit = grunt_at(18, 1);
+    assert_eq!(
+      think_once(&mut unit, &map, &[player_at(1, 10)]),
+      Behavior::Patrol
+    );
+    // Close, but behind the spiral's wall.
+    let mut unit = grunt_at(14, 7);
+    assert_eq!(
+      think_once(&mut unit, &map, &[player_at(9, 7)]),
+      Behavior::Patrol
+    );
+    assert!(!unit.shooting);
+  }
+
+  #[test]
+  fn chases_then_attacks() {
+    let map = test_map();
+    let mut unit = grunt_at(18, 1);
+    assert_eq!(
+      think_once(&mut unit, &map, &[player_at(12, 1)]),
+      Behavior::Chase
+    );
+    assert!(unit.move_dir.x.0 < 0.);
+    assert!(!unit.shooting);
+
+    assert_eq!(
+      think_once(&mut unit, &map, &[player_at(15, 1)]),
+      Behavior::Attack
+    );
+    assert!(unit.shooting);
+    assert!(unit.heading.x.0 < -0.99);
+  }
+
+  #[test]
+  fn flees_when_hurt() {
+    let map = test_map();
+    let mut unit = grunt_at(18, 1);
+    unit.health = 10;
+    assert_eq!(
+      think_once(&mut unit, &map, &[player_at(15, 1)]),
+      Behavior::Flee
+    );
+    assert!(unit.move_dir.x.0 > 0.);
+    assert!(!unit.shooting);
+  }
+}
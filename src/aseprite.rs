@@ -0,0 +1,441 @@
+// Reads the JSON that Aseprite exports alongside a sprite sheet PNG.
+//
+// Export with File > Export Sprite Sheet, with "JSON Data" checked, or from the
+// command line:
+// aseprite -b sprite-sheet.aseprite --sheet sprite-sheet.png \
+//   --data sprite-sheet.json --list-tags --list-slices
+//
+// Both the "Hash" and "Array" JSON layouts are supported. Rotated frames
+// aren't, so leave "Rotated" unchecked.
+//
+// The export becomes sprites and animations like so:
+// - Every frame is a sprite, named after the frame's filename with any
+//   ".aseprite" extension dropped and spaces replaced by underscores. So the
+//   default "sprite-sheet 0.aseprite" becomes "sprite-sheet_0".
+// - Every slice is a sprite too, named after the slice. It covers the slice's
+//   bounds in the first frame it has a key for. This is the way to give a
+//   sprite a stable name like "newt_gingrich".
+// - Every tag is an animation named after the tag, using the tagged frames
+//   with their durations. Tags that repeat a fixed number of times play once
+//   and stop; the rest loop.
+
+use std::fmt;
+
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::sprite_sheet::{Animation, AnimationFrame, SpriteKey, SpriteRef};
+
+// The parts of an export that the game uses.
+pub struct Export {
+  // Path to the sheet image, relative to the JSON file.
+  pub image_path: String,
+  pub sprites: Vec<SpriteRef>,
+  pub animations: Vec<Animation>,
+}
+
+impl Export {
+  pub fn from_json(json: &str) -> Result<Export, String> {
+    let file: File =
+      serde_json::from_str(json).map_err(|e| format!("err parsing aseprite json: {}", e))?;
+    let frames = match file.frames {
+      Frames::Array(frames) => frames,
+      Frames::Hash(OrderedFrames(frames)) => frames,
+    };
+
+    let mut sprites = vec![];
+    for frame in frames.iter() {
+      if frame.rotated {
+        return Err(format!("frame \"{}\" is rotated", frame.filename));
+      }
+      sprites.push(SpriteRef {
+        name: frame_sprite_name(&frame.filename),
+        offset_x: frame.frame.x,
+        offset_y: frame.frame.y,
+        width: frame.frame.w,
+        height: frame.frame.h,
+      });
+    }
+
+    for slice in file.meta.slices.iter() {
+      let key = slice
+        .keys
+        .iter()
+        .min_by_key(|k| k.frame)
+        .ok_or(format!("slice \"{}\" has no keys", slice.name))?;
+      let frame = frames.get(key.frame).ok_or(format!(
+        "slice \"{}\" is keyed on missing frame {}",
+        slice.name, key.frame
+      ))?;
+      // Slice bounds are relative to the untrimmed frame, but the sheet only
+      // holds the trimmed part, which starts at spriteSourceSize.
+      let x = frame.frame.x as i64 + key.bounds.x as i64 - frame.sprite_source_size.x as i64;
+      let y = frame.frame.y as i64 + key.bounds.y as i64 - frame.sprite_source_size.y as i64;
+      if x < 0 || y < 0 {
+        return Err(format!(
+          "slice \"{}\" is outside of frame {}'s trimmed bounds",
+          slice.name, key.frame
+        ));
+      }
+      sprites.push(SpriteRef {
+        name: slice.name.clone(),
+        offset_x: x as u32,
+        offset_y: y as u32,
+        width: key.bounds.w,
+        height: key.bounds.h,
+      });
+    }
+
+    let mut animations = vec![];
+    for tag in file.meta.frame_tags.iter() {
+      if tag.from > tag.to || tag.to >= frames.len() {
+        return Err(format!(
+          "tag \"{}\" has bad frame range {}..{}",
+          tag.name, tag.from, tag.to
+        ));
+      }
+      let forward: Vec<usize> = (tag.from..=tag.to).collect();
+      let order: Vec<usize> = match tag.direction.as_str() {
+        "forward" => forward,
+        "reverse" => forward.into_iter().rev().collect(),
+        // Ping-pong doesn't repeat the frames at either end.
+        "pingpong" => pingpong(&forward),
+        "pingpong_reverse" => pingpong(&forward.into_iter().rev().collect::<Vec<_>>()),
+        other => {
+          return Err(format!(
+            "tag \"{}\" has unknown direction \"{}\"",
+            tag.name, other
+          ))
+        }
+      };
+      animations.push(Animation {
+        name: tag.name.clone(),
+        frames: order
+          .into_iter()
+          .map(|i| AnimationFrame {
+            sprite: SpriteKey::new(&sprites[i].name),
+            duration_ms: frames[i].duration,
+          })
+          .collect(),
+        // Aseprite leaves out "repeat" for tags that loop forever, and uses
+        // "0" for it too.
+        looping: matches!(tag.repeat.as_deref(), None | Some("0")),
+      });
+    }
+
+    Ok(Export {
+      image_path: file.meta.image,
+      sprites,
+      animations,
+    })
+  }
+}
+
+fn frame_sprite_name(filename: &str) -> String {
+  filename
+    .strip_suffix(".aseprite")
+    .unwrap_or(filename)
+    .replace(' ', "_")
+}
+
+// Goes there and back again, eg. [1, 2, 3] becomes [1, 2, 3, 2].
+fn pingpong(frames: &[usize]) -> Vec<usize> {
+  let mut order = frames.to_vec();
+  if frames.len() > 2 {
+    order.extend(frames[1..frames.len() - 1].iter().rev());
+  }
+  order
+}
+
+// The JSON layout. Fields the game doesn't use are left out.
+
+#[derive(Deserialize)]
+struct File {
+  frames: Frames,
+  meta: Meta,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Frames {
+  Array(Vec<Frame>),
+  Hash(OrderedFrames),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Frame {
+  // Only in the array layout. In the hash layout it's the key instead.
+  #[serde(default)]
+  filename: String,
+  frame: Rect,
+  #[serde(default)]
+  rotated: bool,
+  sprite_source_size: Rect,
+  duration: u32,
+}
+
+#[derive(Deserialize)]
+struct Rect {
+  x: u32,
+  y: u32,
+  w: u32,
+  h: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+  image: String,
+  #[serde(default)]
+  frame_tags: Vec<Tag>,
+  #[serde(default)]
+  slices: Vec<Slice>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+  name: String,
+  from: usize,
+  to: usize,
+  direction: String,
+  repeat: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Slice {
+  name: String,
+  keys: Vec<SliceKey>,
+}
+
+#[derive(Deserialize)]
+struct SliceKey {
+  frame: usize,
+  bounds: Rect,
+}
+
+// The frames of the hash layout, in the order they appear in the file. Tags
+// refer to frames by index, so the order matters, and a HashMap would lose it.
+struct OrderedFrames(Vec<Frame>);
+
+impl<'de> Deserialize<'de> for OrderedFrames {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    struct FramesVisitor;
+
+    impl<'de> Visitor<'de> for FramesVisitor {
+      type Value = OrderedFrames;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map from frame filename to frame")
+      }
+
+      fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<OrderedFrames, M::Error> {
+        let mut frames = vec![];
+        while let Some((filename, mut frame)) = map.next_entry::<String, Frame>()? {
+          frame.filename = filename;
+          frames.push(frame);
+        }
+        Ok(OrderedFrames(frames))
+      }
+    }
+
+    deserializer.deserialize_map(FramesVisitor)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A frame as it appears in the export, for the array layout.
+  fn frame(filename: &str, x: u32, duration: u32) -> String {
+    format!(
+      r#"{{ "filename": "{}", "frame": {{ "x": {}, "y": 0, "w": 16, "h": 16 }},
+        "rotated": false, "trimmed": false,
+        "spriteSourceSize": {{ "x": 0, "y": 0, "w": 16, "h": 16 }},
+        "sourceSize": {{ "w": 16, "h": 16 }}, "duration": {} }}"#,
+      filename, x, duration
+    )
+  }
+
+  fn export(frames: &str, tags: &str, slices: &str) -> Result<Export, String> {
+    Export::from_json(&format!(
+      r#"{{ "frames": {},
+        "meta": {{ "image": "sheet.png", "frameTags": [{}], "slices": [{}] }} }}"#,
+      frames, tags, slices
+    ))
+  }
+
+  // Four frames in a row, in the array layout.
+  fn four_frames() -> String {
+    let frames: Vec<_> = (0..4)
+      .map(|i| frame(&format!("walk {}.aseprite", i), i * 16, 100 + i))
+      .collect();
+    format!("[{}]", frames.join(","))
+  }
+
+  fn tag(name: &str, direction: &str) -> String {
+    format!(
+      r#"{{ "name": "{}", "from": 0, "to": 3, "direction": "{}" }}"#,
+      name, direction
+    )
+  }
+
+  fn frame_names(animation: &Animation) -> Vec<&str> {
+    animation
+      .frames
+      .iter()
+      .map(|f| f.sprite.name.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn reads_array_layout() {
+    let export = export(&four_frames(), "", "").unwrap();
+    assert_eq!(export.image_path, "sheet.png");
+    let names: Vec<_> = export.sprites.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["walk_0", "walk_1", "walk_2", "walk_3"]);
+    assert_eq!(export.sprites[2].offset_x, 32);
+    assert_eq!(export.sprites[2].width, 16);
+  }
+
+  #[test]
+  fn hash_layout_keeps_file_order() {
+    // Not in sorted order, which a HashMap or BTreeMap would lose.
+    let hash = format!(
+      r#"{{ "b": {}, "c": {}, "a": {} }}"#,
+      frame("", 0, 10),
+      frame("", 16, 20),
+      frame("", 32, 30)
+    );
+    let tags = r#"{ "name": "all", "from": 0, "to": 2, "direction": "forward" }"#;
+    let export = export(&hash, tags, "").unwrap();
+    let names: Vec<_> = export.sprites.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["b", "c", "a"]);
+    assert_eq!(export.sprites[2].offset_x, 32);
+
+    let durations: Vec<_> = export.animations[0]
+      .frames
+      .iter()
+      .map(|f| f.duration_ms)
+      .collect();
+    assert_eq!(durations, [10, 20, 30]);
+  }
+
+  #[test]
+  fn orders_tag_frames_by_direction() {
+    let tags = [
+      tag("fwd", "forward"),
+      tag("rev", "reverse"),
+      tag("pp", "pingpong"),
+      tag("pprev", "pingpong_reverse"),
+    ];
+    let export = export(&four_frames(), &tags.join(","), "").unwrap();
+    let anims = &export.animations;
+    assert_eq!(
+      frame_names(&anims[0]),
+      ["walk_0", "walk_1", "walk_2", "walk_3"]
+    );
+    assert_eq!(
+      frame_names(&anims[1]),
+      ["walk_3", "walk_2", "walk_1", "walk_0"]
+    );
+    assert_eq!(
+      frame_names(&anims[2]),
+      ["walk_0", "walk_1", "walk_2", "walk_3", "walk_2", "walk_1"]
+    );
+    assert_eq!(
+      frame_names(&anims[3]),
+      ["walk_3", "walk_2", "walk_1", "walk_0", "walk_1", "walk_2"]
+    );
+    assert_eq!(anims[2].frames[4].duration_ms, 102);
+  }
+
+  #[test]
+  fn pingpongs_short_tags() {
+    assert_eq!(pingpong(&[5]), [5]);
+    assert_eq!(pingpong(&[5, 6]), [5, 6]);
+    assert_eq!(pingpong(&[5, 6, 7]), [5, 6, 7, 6]);
+  }
+
+  #[test]
+  fn only_repeat_0_or_none_loops() {
+    let tags = [
+      tag("forever", "forward"),
+      r#"{ "name": "zero", "from": 0, "to": 1, "direction": "forward", "repeat": "0" }"#
+        .to_string(),
+      r#"{ "name": "twice", "from": 0, "to": 1, "direction": "forward", "repeat": "2" }"#
+        .to_string(),
+    ];
+    let export = export(&four_frames(), &tags.join(","), "").unwrap();
+    let looping: Vec<_> = export.animations.iter().map(|a| a.looping).collect();
+    assert_eq!(looping, [true, true, false]);
+  }
+
+  #[test]
+  fn slices_use_their_first_keyed_frame() {
+    // Frame 1 is trimmed: its sheet rect starts 2px into the frame.
+    let frames = format!(
+      r#"[{}, {{ "filename": "f1", "frame": {{ "x": 20, "y": 10, "w": 12, "h": 12 }},
+        "spriteSourceSize": {{ "x": 2, "y": 3, "w": 12, "h": 12 }}, "duration": 100 }}]"#,
+      frame("f0", 0, 100)
+    );
+    let slices = r#"{ "name": "head", "keys": [
+        { "frame": 2, "bounds": { "x": 0, "y": 0, "w": 1, "h": 1 } },
+        { "frame": 1, "bounds": { "x": 4, "y": 5, "w": 6, "h": 7 } }
+      ] }"#;
+    let export = export(&frames, "", slices).unwrap();
+    let head = export.sprites.iter().find(|s| s.name == "head").unwrap();
+    assert_eq!(
+      (head.offset_x, head.offset_y, head.width, head.height),
+      (22, 12, 6, 7)
+    );
+  }
+
+  #[test]
+  fn rejects_bad_exports() {
+    let slice = |frame: u32, x: u32| {
+      format!(
+        r#"{{ "name": "s", "keys": [{{ "frame": {}, "bounds": {{ "x": {}, "y": 0, "w": 1, "h": 1 }} }}] }}"#,
+        frame, x
+      )
+    };
+    let trimmed = r#"[{ "filename": "t", "frame": { "x": 0, "y": 0, "w": 4, "h": 4 },
+        "spriteSourceSize": { "x": 3, "y": 0, "w": 4, "h": 4 }, "duration": 1 }]"#;
+    let rotated = four_frames().replacen(r#""rotated": false"#, r#""rotated": true"#, 1);
+    let bad_range = r#"{ "name": "t", "from": 2, "to": 4, "direction": "forward" }"#;
+
+    assert_eq!(
+      export(&rotated, "", "").err().unwrap(),
+      "frame \"walk 0.aseprite\" is rotated"
+    );
+    assert_eq!(
+      export(&four_frames(), bad_range, "").err().unwrap(),
+      "tag \"t\" has bad frame range 2..4"
+    );
+    assert_eq!(
+      export(&four_frames(), &tag("t", "sideways"), "")
+        .err()
+        .unwrap(),
+      "tag \"t\" has unknown direction \"sideways\""
+    );
+    assert_eq!(
+      export(&four_frames(), "", r#"{ "name": "s", "keys": [] }"#)
+        .err()
+        .unwrap(),
+      "slice \"s\" has no keys"
+    );
+    assert_eq!(
+      export(&four_frames(), "", &slice(4, 0)).err().unwrap(),
+      "slice \"s\" is keyed on missing frame 4"
+    );
+    assert_eq!(
+      export(trimmed, "", &slice(0, 1)).err().unwrap(),
+      "slice \"s\" is outside of frame 0's trimmed bounds"
+    );
+    assert!(Export::from_json("{}")
+      .err()
+      .unwrap()
+      .starts_with("err parsing aseprite json"));
+  }
+}
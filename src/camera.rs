@@ -0,0 +1,176 @@
+use crate::dimensions::{
+  WorldCoord as Coord, WorldPoint as Point, WorldRect as Rect, PIXELS_PER_WORLD,
+};
+use crate::game;
+
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.;
+// Each notch of the mouse wheel zooms by this factor.
+const ZOOM_STEP: f32 = 1.1;
+// When fitting players on screen, keep them at least this far from the edge.
+const FIT_MARGIN: Coord = Coord(100.);
+
+// What the camera keeps in the middle of the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraTarget {
+  // The centroid of all living players.
+  AllPlayers,
+  // Just the one player, by index into game.players.
+  Player(usize),
+}
+
+// The view of the world shown in the window.
+//
+// All conversions between world and window coordinates go through here, so
+// that everything agrees on what's on screen.
+pub struct Camera {
+  // World point at the top-left corner of the window.
+  pub top_left: Point,
+  // Size of the window, in pixels.
+  pub window_width: u32,
+  pub window_height: u32,
+  pub target: CameraTarget,
+
+  // The zoom the user asked for. 1 is the default, >1 is zoomed in.
+  pub zoom: f32,
+  // If set, zoom out past the user's zoom when needed to keep all the players
+  // on screen.
+  pub fit_players: bool,
+  // The zoom actually in use, after fitting players.
+  effective_zoom: f32,
+}
+
+impl Camera {
+  pub fn new(window_width: u32, window_height: u32) -> Camera {
+    Camera {
+      top_left: Point::new(Coord(0.), Coord(0.)),
+      window_width,
+      window_height,
+      target: CameraTarget::AllPlayers,
+
+      zoom: 1.,
+      fit_players: false,
+      effective_zoom: 1.,
+    }
+  }
+
+  pub fn pixels_per_world(&self) -> f32 {
+    PIXELS_PER_WORLD * self.effective_zoom
+  }
+
+  // Width and height of the view, in world units.
+  pub fn view_width(&self) -> Coord {
+    Coord(self.window_width as f32 / self.pixels_per_world())
+  }
+  pub fn view_height(&self) -> Coord {
+    Coord(self.window_height as f32 / self.pixels_per_world())
+  }
+
+  // The part of the world that's visible in the window.
+  pub fn view_rect(&self) -> Rect {
+    Rect {
+      top_left: self.top_left,
+      width: self.view_width(),
+      height: self.view_height(),
+    }
+  }
+
+  // Zooms in by steps notches, or out if steps is negative.
+  pub fn zoom_by(&mut self, steps: i32) {
+    self.zoom = (self.zoom * ZOOM_STEP.powi(steps)).clamp(MIN_ZOOM, MAX_ZOOM);
+  }
+
+  // Moves the camera to center on its target, then clamps it so it doesn't
+  // show anything past the edge of the map. If nobody is alive to follow, the
+  // camera stays where it is.
+  //
+  // This is also where zoom changes take effect, including zooming out to fit
+  // the players if fit_players is set.
+  //
+  // Positions are interpolated with tick_alpha like the rest of rendering, so
+  // the camera moves smoothly between ticks.
+  pub fn follow(&mut self, game: &game::State, tick_alpha: f32) {
+    let units = game
+      .players
+      .iter()
+      .enumerate()
+      .filter(|(i, _)| match self.target {
+        CameraTarget::AllPlayers => true,
+        CameraTarget::Player(p) => *i == p,
+      })
+      .map(|(_, p)| &p.unit)
+      .filter(|u| u.is_alive());
+
+    let positions: Vec<_> = units.map(|u| u.interpolated_pos(tick_alpha)).collect();
+    self.effective_zoom = self.zoom;
+    if positions.is_empty() {
+      return;
+    }
+
+    let mut sum = Point::new(Coord(0.), Coord(0.));
+    for p in positions.iter() {
+      sum = sum + *p;
+    }
+    let center = sum / Coord(positions.len() as f32);
+
+    if self.fit_players {
+      self.effective_zoom = f32::min(self.zoom, self.zoom_to_fit(center, &positions));
+    }
+
+    let half_view = Point::new(self.view_width(), self.view_height()) / Coord(2.);
+    self.top_left = center - half_view;
+    self.clamp_to(&game.map.bounds());
+  }
+
+  // Returns the largest zoom that shows every point in positions, with some
+  // margin, when the view is centered on center.
+  fn zoom_to_fit(&self, center: Point, positions: &[Point]) -> f32 {
+    let mut half_width = Coord(0.);
+    let mut half_height = Coord(0.);
+    for p in positions.iter() {
+      let offset = *p - center;
+      half_width = Coord(half_width.0.max(offset.x.0.abs()));
+      half_height = Coord(half_height.0.max(offset.y.0.abs()));
+    }
+    let width = (half_width + FIT_MARGIN) * Coord(2.);
+    let height = (half_height + FIT_MARGIN) * Coord(2.);
+    let zoom_x = self.window_width as f32 / (width.0 * PIXELS_PER_WORLD);
+    let zoom_y = self.window_height as f32 / (height.0 * PIXELS_PER_WORLD);
+    f32::min(zoom_x, zoom_y).max(MIN_ZOOM)
+  }
+
+  // Keeps the view inside bounds. On any axis where the view is bigger than
+  // bounds, the bounds are centered instead.
+  fn clamp_to(&mut self, bounds: &Rect) {
+    let clamp_axis = |pos: Coord, view: Coord, lower: Coord, size: Coord| {
+      if view >= size {
+        lower - (view - size) / Coord(2.)
+      } else {
+        pos.clamp(lower, lower + size - view)
+      }
+    };
+    self.top_left = Point::new(
+      clamp_axis(
+        self.top_left.x,
+        self.view_width(),
+        bounds.top_left.x,
+        bounds.width,
+      ),
+      clamp_axis(
+        self.top_left.y,
+        self.view_height(),
+        bounds.top_left.y,
+        bounds.height,
+      ),
+    );
+  }
+
+  // Cycles through following everyone, then each player in turn.
+  pub fn cycle_target(&mut self, n_players: usize) {
+    self.target = match self.target {
+      CameraTarget::AllPlayers if n_players > 0 => CameraTarget::Player(0),
+      CameraTarget::Player(p) if p + 1 < n_players => CameraTarget::Player(p + 1),
+      _ => CameraTarget::AllPlayers,
+    };
+  }
+}
@@ -1,10 +1,13 @@
 pub use sdl2::rect::Point as WindowPoint;
 pub use sdl2::rect::Rect as WindowRect;
 
+use crate::camera::Camera;
+
 use std::cmp::{Ordering, PartialOrd};
 use std::ops::{Add, Div, Mul, Neg, Sub, SubAssign};
 
-const PIXELS_PER_WORLD: f32 = 1.;
+// Window pixels per world unit at a camera zoom of 1.
+pub const PIXELS_PER_WORLD: f32 = 1.;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct WorldCoord(pub f32);
@@ -54,12 +57,12 @@ impl WorldCoord {
     WorldCoord(self.0.clamp(lower.0, upper.0))
   }
 
-  pub fn to_window_as_dim(self) -> u32 {
-    (self.0 * PIXELS_PER_WORLD) as u32
+  pub fn to_window_as_dim(self, camera: &Camera) -> u32 {
+    (self.0 * camera.pixels_per_world()) as u32
   }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct WorldPoint {
   pub x: WorldCoord,
   pub y: WorldCoord,
@@ -83,11 +86,16 @@ impl WorldPoint {
     self / magnitude
   }
 
-  pub fn to_window(self, camera: WorldPoint) -> WindowPoint {
-    let offset = self - camera;
+  // Linear interpolation from self (t=0) to other (t=1).
+  pub fn lerp(self, other: WorldPoint, t: f32) -> WorldPoint {
+    self + (other - self) * WorldCoord(t)
+  }
+
+  pub fn to_window(self, camera: &Camera) -> WindowPoint {
+    let offset = self - camera.top_left;
     WindowPoint::new(
-      (offset.x.0 * PIXELS_PER_WORLD) as i32,
-      (offset.y.0 * PIXELS_PER_WORLD) as i32,
+      (offset.x.0 * camera.pixels_per_world()).floor() as i32,
+      (offset.y.0 * camera.pixels_per_world()).floor() as i32,
     )
   }
 
@@ -143,19 +151,19 @@ impl SubAssign for WorldPoint {
 }
 
 pub trait ToWorld {
-  fn to_world(self, camera: WorldPoint) -> WorldPoint;
+  fn to_world(self, camera: &Camera) -> WorldPoint;
 }
 
 impl ToWorld for WindowPoint {
-  fn to_world(self, camera: WorldPoint) -> WorldPoint {
+  fn to_world(self, camera: &Camera) -> WorldPoint {
     WorldPoint {
-      x: WorldCoord(self.x() as f32 / PIXELS_PER_WORLD),
-      y: WorldCoord(self.y() as f32 / PIXELS_PER_WORLD),
-    } + camera
+      x: WorldCoord(self.x() as f32 / camera.pixels_per_world()),
+      y: WorldCoord(self.y() as f32 / camera.pixels_per_world()),
+    } + camera.top_left
   }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct WorldRect {
   pub top_left: WorldPoint,
   pub width: WorldCoord,
@@ -170,32 +178,67 @@ impl WorldRect {
       && p.y <= self.top_left.y + self.height
   }
 
+  // True if the rects overlap or touch. Like contains, edges are inclusive.
   pub fn intersects(&self, other: &WorldRect) -> bool {
-    let (p1, p2, p3, p4) = self.points();
-    let (q1, q2, q3, q4) = other.points();
-    other.contains(p1)
-      || other.contains(p2)
-      || other.contains(p3)
-      || other.contains(p4)
-      || self.contains(q1)
-      || self.contains(q2)
-      || self.contains(q3)
-      || self.contains(q4)
-  }
-
-  fn points(&self) -> (WorldPoint, WorldPoint, WorldPoint, WorldPoint) {
-    (
-      self.top_left,
-      self.top_left + WorldPoint::new(self.width, WorldCoord(0.)),
-      self.top_left + WorldPoint::new(WorldCoord(0.), self.height),
-      self.top_left + WorldPoint::new(self.width, self.height),
-    )
+    // Checking for corners inside each other would miss rects that cross like
+    // a plus sign, so compare the spans on each axis instead.
+    self.top_left.x <= other.top_left.x + other.width
+      && other.top_left.x <= self.top_left.x + self.width
+      && self.top_left.y <= other.top_left.y + other.height
+      && other.top_left.y <= self.top_left.y + self.height
+  }
+
+  // Moves the point p along delta, and returns when it first touches the
+  // rect, as a fraction of delta from 0 to 1, along with the outward normal of
+  // the side it touched. Like contains, edges are inclusive. A point that
+  // starts in the rect touches it straight away.
+  pub fn sweep_point(&self, p: WorldPoint, delta: WorldPoint) -> Option<(f32, WorldPoint)> {
+    let bot_right = self.top_left + WorldPoint::new(self.width, self.height);
+    let axes = [
+      (p.x.0, delta.x.0, self.top_left.x.0, bot_right.x.0),
+      (p.y.0, delta.y.0, self.top_left.y.0, bot_right.y.0),
+    ];
+    // The span of time that p is within the rect's span on every axis so far.
+    let mut enter = f32::NEG_INFINITY;
+    let mut exit = f32::INFINITY;
+    let mut normal = WorldPoint::new(WorldCoord(0.), WorldCoord(0.));
+    for (axis, (p, delta, min, max)) in axes.into_iter().enumerate() {
+      if delta == 0. {
+        if p < min || max < p {
+          return None;
+        }
+        continue;
+      }
+      let (t_min, t_max) = ((min - p) / delta, (max - p) / delta);
+      // Moving in +x, p comes in through the rect's -x side, and so on.
+      let (near, far, side) = if delta > 0. {
+        (t_min, t_max, -1.)
+      } else {
+        (t_max, t_min, 1.)
+      };
+      if near > enter {
+        enter = near;
+        normal = if axis == 0 {
+          WorldPoint::new(WorldCoord(side), WorldCoord(0.))
+        } else {
+          WorldPoint::new(WorldCoord(0.), WorldCoord(side))
+        };
+      }
+      exit = exit.min(far);
+    }
+    if enter > exit || exit < 0. || enter > 1. {
+      return None;
+    }
+    Some((enter.max(0.), normal))
   }
 
-  pub fn to_window_rect(&self, camera_pos: WorldPoint) -> WindowRect {
-    let top_left = self.top_left.to_window(camera_pos);
-    let w = self.width.to_window_as_dim();
-    let h = self.height.to_window_as_dim();
+  pub fn to_window_rect(self, camera: &Camera) -> WindowRect {
+    // Converting both corners, rather than the top-left corner and the size,
+    // keeps adjacent rects from leaving gaps between them when zoomed.
+    let top_left = self.top_left.to_window(camera);
+    let bot_right = (self.top_left + WorldPoint::new(self.width, self.height)).to_window(camera);
+    let w = (bot_right.x - top_left.x).max(0) as u32;
+    let h = (bot_right.y - top_left.y).max(0) as u32;
     WindowRect::new(top_left.x, top_left.y, w, h)
   }
 }
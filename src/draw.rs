@@ -6,53 +6,200 @@ use sdl2::render::{Canvas, Texture, TextureCreator};
 use sdl2::ttf::Font;
 use sdl2::video::{Window, WindowContext};
 
-use crate::dimensions::WindowPoint;
+use crate::dimensions::{ToWorld, WindowPoint, WorldRect};
 use crate::game;
-use crate::map::{GridTile, TILE_WIDTH};
+use crate::map::{GridTile, PickupKind, ToTilePoint, TILE_WIDTH_F32};
+use crate::sprite_registry::SpriteRegistry;
+use crate::sprite_sheet::SpriteHandle;
 use crate::State;
 
 const EMPTY_TILE_COLOR: Color = Color::RGB(40, 42, 54);
 const OBSTACLE_COLOR: Color = Color::RGB(255, 184, 108);
 const UNIT_COLOR: Color = Color::RGB(255, 121, 198);
 const BULLET_COLOR: Color = Color::RGB(255, 121, 0);
+const WATER_COLOR: Color = Color::RGB(98, 114, 164);
+const MUD_COLOR: Color = Color::RGB(110, 84, 60);
+const HEALTH_PICKUP_COLOR: Color = Color::RGB(80, 250, 123);
+
+const PLAYER_SPAWN_COLOR: Color = Color::RGB(139, 233, 253);
 
 const COLOR_WHITE: Color = Color::RGB(248, 248, 242);
 
+// Optional sprites. If no sprite sheet has these, we fall back to
+// filling with the colors above.
+const BULLET_SPRITE: &str = "bullet";
+const EMPTY_TILE_SPRITE: &str = "tile_empty";
+const OBSTACLE_SPRITE: &str = "tile_obstacle";
+const WATER_SPRITE: &str = "tile_water";
+const MUD_SPRITE: &str = "tile_mud";
+const HEALTH_PICKUP_SPRITE: &str = "pickup_health";
+
+// Handles for the optional sprites above, looked up once at load time.
+pub struct OptionalSprites {
+  bullet: Option<SpriteHandle>,
+  empty_tile: Option<SpriteHandle>,
+  obstacle: Option<SpriteHandle>,
+  water: Option<SpriteHandle>,
+  mud: Option<SpriteHandle>,
+  health_pickup: Option<SpriteHandle>,
+}
+
+impl OptionalSprites {
+  pub fn from_registry(registry: &SpriteRegistry) -> OptionalSprites {
+    OptionalSprites {
+      bullet: registry.sprite(BULLET_SPRITE).ok(),
+      empty_tile: registry.sprite(EMPTY_TILE_SPRITE).ok(),
+      obstacle: registry.sprite(OBSTACLE_SPRITE).ok(),
+      water: registry.sprite(WATER_SPRITE).ok(),
+      mud: registry.sprite(MUD_SPRITE).ok(),
+      health_pickup: registry.sprite(HEALTH_PICKUP_SPRITE).ok(),
+    }
+  }
+}
+
+// The direction unit sprites face in the sprite sheet, in degrees clockwise
+// from the +x axis.
+const UNIT_SPRITE_FACING: f64 = 0.;
+
 pub fn draw_frame(canvas: &mut Canvas<Window>, state: &mut State) {
   canvas.set_draw_color(Color::BLACK);
   canvas.clear();
 
-  for tile in state.game.map.tiles() {
-    canvas.set_draw_color(match tile.tile {
-      GridTile::Empty => EMPTY_TILE_COLOR,
-      GridTile::Obstacle => OBSTACLE_COLOR,
-    });
-    let window_pos = tile.pos.to_world_point().to_window(state.camera_pos());
-    let _ = canvas.fill_rect(Rect::new(
-      window_pos.x(),
-      window_pos.y(),
-      TILE_WIDTH,
-      TILE_WIDTH,
-    ));
+  // Only draw what's on screen.
+  let view = state.camera.view_rect();
+
+  for tile in state.game.map.tiles_overlapping_rect(view) {
+    let (sprite, color) = match tile.tile {
+      GridTile::Empty => (state.sprites.empty_tile, EMPTY_TILE_COLOR),
+      GridTile::Obstacle => (state.sprites.obstacle, OBSTACLE_COLOR),
+      GridTile::Water => (state.sprites.water, WATER_COLOR),
+      GridTile::Mud => (state.sprites.mud, MUD_COLOR),
+    };
+    let rect = tile.pos.to_world_rect().to_window_rect(&state.camera);
+    draw_sprite_or_fill(canvas, state, sprite, rect, color);
+  }
+
+  for pickup in state.game.pickups.iter() {
+    let bounds = pickup.bounding_box();
+    if !view.intersects(&bounds) {
+      continue;
+    }
+    let (sprite, color) = match pickup.kind {
+      PickupKind::Health => (state.sprites.health_pickup, HEALTH_PICKUP_COLOR),
+    };
+    let rect = bounds.to_window_rect(&state.camera);
+    draw_sprite_or_fill(canvas, state, sprite, rect, color);
   }
 
   for player in state.game.players.iter() {
-    draw_unit(canvas, state, &player.unit);
+    draw_unit(canvas, state, &view, &player.unit);
   }
   for unit in state.game.units.iter() {
-    draw_unit(canvas, state, unit);
+    draw_unit(canvas, state, &view, unit);
   }
   for bullet in state.game.bullets.iter() {
-    canvas.set_draw_color(BULLET_COLOR);
-    let bounds = bullet.bounding_box();
-    let _ = canvas.fill_rect(bounds.to_window_rect(state.camera_pos));
+    let bounds = bullet.bounding_box_at(bullet.interpolated_pos(state.tick_alpha));
+    if !view.intersects(&bounds) {
+      continue;
+    }
+    let rect = bounds.to_window_rect(&state.camera);
+    draw_sprite_or_fill(canvas, state, state.sprites.bullet, rect, BULLET_COLOR);
+  }
+
+  if state.editor.open {
+    draw_editor(canvas, state);
   }
 }
 
-fn draw_unit(canvas: &mut Canvas<Window>, state: &State, unit: &game::Unit) {
-  canvas.set_draw_color(UNIT_COLOR);
-  let bounds = unit.bounding_box();
-  let _ = canvas.fill_rect(bounds.to_window_rect(state.camera_pos));
+// Shows where the player spawns are and which tile the mouse is over, with a
+// line about what the editor is doing.
+fn draw_editor(canvas: &mut Canvas<Window>, state: &mut State) {
+  let map = &state.game.map;
+  canvas.set_draw_color(PLAYER_SPAWN_COLOR);
+  for spawn in map.player_spawns.iter() {
+    let center = spawn.center_to_world_point().to_window(&state.camera);
+    let rad = (state.camera.pixels_per_world() * TILE_WIDTH_F32 / 4.) as u32;
+    let _ = canvas.draw_rect(rect_from_center_rad(center, rad));
+  }
+
+  let mouse = state.mouse_pos.to_world(&state.camera);
+  if map.bounds().contains(mouse) {
+    let rect = mouse
+      .to_tile_point()
+      .to_world_rect()
+      .to_window_rect(&state.camera);
+    canvas.set_draw_color(COLOR_WHITE);
+    let _ = canvas.draw_rect(rect);
+  }
+
+  let editor = &state.editor;
+  let text = format!(
+    "editing {}{} ({}x{}), tool: {}",
+    editor.path,
+    if editor.dirty { "*" } else { "" },
+    map.width,
+    map.height,
+    editor.tool.name()
+  );
+  let _ = state
+    .text_renderer
+    .draw_to_canvas(canvas, &state.font, &text, WindowPoint::new(8, 8));
+}
+
+fn draw_sprite_or_fill(
+  canvas: &mut Canvas<Window>,
+  state: &State,
+  sprite: Option<SpriteHandle>,
+  rect: Rect,
+  color: Color,
+) {
+  match sprite {
+    Some(sprite) => {
+      let _ = state
+        .sprite_registry
+        .blit_sprite_to_rect(sprite, canvas, rect);
+    }
+    None => {
+      canvas.set_draw_color(color);
+      let _ = canvas.fill_rect(rect);
+    }
+  }
+}
+
+fn draw_unit(canvas: &mut Canvas<Window>, state: &State, view: &WorldRect, unit: &game::Unit) {
+  let bounds = unit.bounding_box_at(unit.interpolated_pos(state.tick_alpha));
+  if !view.intersects(&bounds) {
+    return;
+  }
+  let rect = bounds.to_window_rect(&state.camera);
+  let sprite = match state.sprite_registry.lookup(&unit.sprite_key) {
+    Some(sprite) => sprite,
+    None => {
+      // Missing sprite. Draw a placeholder so the unit is still visible.
+      if unit.is_alive() {
+        canvas.set_draw_color(UNIT_COLOR);
+        let _ = canvas.fill_rect(rect);
+      }
+      return;
+    }
+  };
+
+  // Use the animation for what the unit is doing if there is one, or else its
+  // static sprite. Dead units are only drawn if they have a death animation.
+  let anim = state
+    .sprite_registry
+    .animation_for(sprite, unit.anim.action.anim_suffix());
+  let frame = match anim {
+    Some(anim) => anim.frame_at(unit.anim.elapsed_ms(state.tick_alpha)),
+    None if !unit.is_alive() => return,
+    None => sprite,
+  };
+
+  let heading = unit.heading;
+  let angle = f64::atan2(heading.y.0 as f64, heading.x.0 as f64).to_degrees() - UNIT_SPRITE_FACING;
+  let _ = state
+    .sprite_registry
+    .blit_sprite_to_rect_rotated(frame, canvas, rect, angle);
 }
 
 fn rect_from_points(p1: WindowPoint, p2: WindowPoint) -> Rect {
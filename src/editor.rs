@@ -0,0 +1,580 @@
+// The in-game map editor. While it's open the game is paused, and the mouse
+// edits the map instead of aiming.
+//
+// Controls:
+// - 1 to 4 pick a tile to paint: floor, wall, water or mud. 5 picks player
+//   spawns.
+// - Left click paints the picked tile under the mouse, or places a player
+//   spawn there. Hold and drag to paint a line of tiles. Right click paints
+//   floor instead, or removes player spawns.
+// - Ctrl+arrow keys grow and shrink the map from its right and bottom edges.
+// - Ctrl+Z undoes, and Ctrl+Y or Ctrl+Shift+Z redoes.
+// - Ctrl+S saves the map.
+//
+// Tiles that would block units aren't painted under spawns and pickups, and
+// player spawns aren't removed or cut off by shrinking the map if that would
+// leave fewer than game::N_PLAYERS, so the map always stays loadable. Nor are
+// blocking tiles painted under living units, so they don't get stuck. The map
+// can't grow past MAX_MAP_SIDE.
+// Units and bullets that end up off the map or stuck after resizing, undoing
+// or redoing are moved back onto it with game::State::fit_to_map.
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+
+use crate::camera::Camera;
+use crate::dimensions::{ToWorld, WindowPoint, WorldRect};
+use crate::game;
+use crate::map::{GridTile, Map, MapEntity, TilePoint, ToTilePoint, MAX_MAP_SIDE};
+
+// How many edits can be undone.
+const MAX_UNDO: usize = 100;
+
+// What clicking on the map does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tool {
+  Paint(GridTile),
+  PlayerSpawn,
+}
+
+impl Tool {
+  pub fn name(self) -> &'static str {
+    match self {
+      Tool::Paint(tile) => tile.name(),
+      Tool::PlayerSpawn => "player_spawn",
+    }
+  }
+}
+
+pub struct Editor {
+  pub open: bool,
+  pub tool: Tool,
+  // Where the map is saved to.
+  pub path: String,
+  // Whether there are edits that haven't been saved.
+  pub dirty: bool,
+
+  // Snapshots of the map from before each edit, oldest first.
+  undo: Vec<Map>,
+  // Snapshots of the map from before each undo, oldest first.
+  redo: Vec<Map>,
+  stroke: Option<Stroke>,
+}
+
+// A drag with a mouse button held, which is undone all at once.
+struct Stroke {
+  button: MouseButton,
+  before: Map,
+  changed: bool,
+}
+
+impl Editor {
+  // A closed editor for the map loaded from path. Maps from Tiled are saved
+  // next to the original as a .txt file, since we can't write Tiled's formats.
+  pub fn new(path: &str) -> Editor {
+    let path = match path.rsplit_once('.') {
+      Some((stem, "tmx" | "tmj" | "json")) => format!("{}.txt", stem),
+      _ => path.to_string(),
+    };
+    Editor {
+      open: false,
+      tool: Tool::Paint(GridTile::Obstacle),
+      path,
+      dirty: false,
+
+      undo: vec![],
+      redo: vec![],
+      stroke: None,
+    }
+  }
+
+  // Handles an event, editing the game's map if it calls for it. Returns
+  // whether the event was the editor's, in which case nothing else should act
+  // on it.
+  pub fn handle_event(&mut self, game: &mut game::State, camera: &Camera, event: &Event) -> bool {
+    match event {
+      Event::KeyDown {
+        keycode: Some(keycode),
+        keymod,
+        ..
+      } => {
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+        self.handle_key(game, *keycode, ctrl, shift)
+      }
+      Event::MouseButtonDown {
+        mouse_btn, x, y, ..
+      } => {
+        if !matches!(mouse_btn, MouseButton::Left | MouseButton::Right) {
+          return false;
+        }
+        self.stroke = Some(Stroke {
+          button: *mouse_btn,
+          before: game.map.clone(),
+          changed: false,
+        });
+        self.apply_stroke(game, camera, WindowPoint::new(*x, *y));
+        true
+      }
+      Event::MouseMotion { x, y, .. } => {
+        self.apply_stroke(game, camera, WindowPoint::new(*x, *y));
+        false
+      }
+      Event::MouseButtonUp { mouse_btn, .. } => match self.stroke.take() {
+        Some(stroke) if stroke.button == *mouse_btn => {
+          if stroke.changed {
+            self.push_undo(stroke.before);
+          }
+          true
+        }
+        other => {
+          self.stroke = other;
+          false
+        }
+      },
+      _ => false,
+    }
+  }
+
+  fn handle_key(
+    &mut self,
+    game: &mut game::State,
+    keycode: Keycode,
+    ctrl: bool,
+    shift: bool,
+  ) -> bool {
+    let tool = match keycode {
+      Keycode::Num1 => Some(Tool::Paint(GridTile::Empty)),
+      Keycode::Num2 => Some(Tool::Paint(GridTile::Obstacle)),
+      Keycode::Num3 => Some(Tool::Paint(GridTile::Water)),
+      Keycode::Num4 => Some(Tool::Paint(GridTile::Mud)),
+      Keycode::Num5 => Some(Tool::PlayerSpawn),
+      _ => None,
+    };
+    if let Some(tool) = tool {
+      self.tool = tool;
+      return true;
+    }
+    if !ctrl {
+      return false;
+    }
+
+    let map = &mut game.map;
+    let (width, height) = (map.width, map.height);
+    match keycode {
+      Keycode::Z if shift => self.redo(map),
+      Keycode::Z => self.undo(map),
+      Keycode::Y => self.redo(map),
+      Keycode::S => self.save(map),
+      Keycode::Right if width < MAX_MAP_SIDE => self.resize(map, width + 1, height),
+      Keycode::Left if width > 1 => self.resize(map, width - 1, height),
+      Keycode::Down if height < MAX_MAP_SIDE => self.resize(map, width, height + 1),
+      Keycode::Up if height > 1 => self.resize(map, width, height - 1),
+      Keycode::Right | Keycode::Left | Keycode::Down | Keycode::Up => {}
+      _ => return false,
+    }
+    game.fit_to_map();
+    true
+  }
+
+  // Applies the current tool at window point p, if a stroke is under way.
+  fn apply_stroke(&mut self, game: &mut game::State, camera: &Camera, p: WindowPoint) {
+    let button = match &self.stroke {
+      Some(stroke) => stroke.button,
+      None => return,
+    };
+    let world = p.to_world(camera);
+    if !game.map.bounds().contains(world) {
+      return;
+    }
+    let units = game.living_unit_boxes();
+    let changed = apply_tool(
+      &mut game.map,
+      &units,
+      self.tool,
+      button,
+      world.to_tile_point(),
+    );
+    if let Some(stroke) = &mut self.stroke {
+      stroke.changed |= changed;
+    }
+    self.dirty |= changed;
+  }
+
+  // Resizes the map, unless that would cut off too many player spawns.
+  fn resize(&mut self, map: &mut Map, width: u32, height: u32) {
+    let n_spawns = map
+      .player_spawns
+      .iter()
+      .filter(|p| p.x() < width && p.y() < height)
+      .count();
+    if !enough_spawns(map.player_spawns.len(), n_spawns) {
+      return;
+    }
+    self.push_undo(map.clone());
+    map.resize(width, height);
+    self.dirty = true;
+  }
+
+  // Remembers before as the state to go back to on undo. Any redo history is
+  // lost, as it no longer follows on from the current map.
+  fn push_undo(&mut self, before: Map) {
+    if self.undo.len() == MAX_UNDO {
+      self.undo.remove(0);
+    }
+    self.undo.push(before);
+    self.redo.clear();
+  }
+
+  fn undo(&mut self, map: &mut Map) {
+    if let Some(before) = self.undo.pop() {
+      self.redo.push(std::mem::replace(map, before));
+      self.dirty = true;
+    }
+  }
+
+  fn redo(&mut self, map: &mut Map) {
+    if let Some(after) = self.redo.pop() {
+      self.undo.push(std::mem::replace(map, after));
+      self.dirty = true;
+    }
+  }
+
+  fn save(&mut self, map: &Map) {
+    if map.player_spawns.len() < game::N_PLAYERS {
+      println!(
+        "error saving map \"{}\": it has {} player spawns, but needs {}",
+        self.path,
+        map.player_spawns.len(),
+        game::N_PLAYERS
+      );
+      return;
+    }
+    match map.save(&self.path) {
+      Ok(()) => {
+        println!("saved map to \"{}\"", self.path);
+        self.dirty = false;
+      }
+      Err(e) => println!("error saving map \"{}\": {}", self.path, e),
+    }
+  }
+}
+
+// Uses tool on the tile at p, as with a click of button. units are the bounding
+// boxes of the living units, which tiles that block movement can't be painted
+// under. Returns whether the map changed.
+fn apply_tool(
+  map: &mut Map,
+  units: &[WorldRect],
+  tool: Tool,
+  button: MouseButton,
+  p: TilePoint,
+) -> bool {
+  match (tool, button) {
+    (Tool::Paint(tile), _) => {
+      let tile = if button == MouseButton::Left {
+        tile
+      } else {
+        GridTile::Empty
+      };
+      let old = match map.get_tile(p) {
+        Some(old) if old != tile => old,
+        _ => return false,
+      };
+      let rect = p.to_world_rect();
+      if tile.blocks_movement() && units.iter().any(|u| u.intersects(&rect)) {
+        return false;
+      }
+      map.set_tile(p, tile);
+      if !entities_at(map, p).all(|e| map.check_entity(&e).is_ok()) {
+        map.set_tile(p, old);
+        return false;
+      }
+      true
+    }
+    (Tool::PlayerSpawn, MouseButton::Left) => {
+      let spawn = MapEntity::PlayerSpawn(p);
+      if map.player_spawns.contains(&p) || map.check_entity(&spawn).is_err() {
+        return false;
+      }
+      map.player_spawns.push(p);
+      true
+    }
+    (Tool::PlayerSpawn, _) => {
+      let n_spawns = map.player_spawns.len();
+      let n_left = map.player_spawns.iter().filter(|s| **s != p).count();
+      if n_left == n_spawns || !enough_spawns(n_spawns, n_left) {
+        return false;
+      }
+      map.player_spawns.retain(|s| *s != p);
+      true
+    }
+  }
+}
+
+// Whether an edit that takes a map from before to after player spawns leaves
+// enough for every player. Maps that already had too few can still be edited,
+// so long as it doesn't lose them any more.
+fn enough_spawns(before: usize, after: usize) -> bool {
+  after >= game::N_PLAYERS || after >= before
+}
+
+// The entities placed on the tile at p, which need it to stay standable.
+fn entities_at(map: &Map, p: TilePoint) -> impl Iterator<Item = MapEntity> + '_ {
+  let spawns = map
+    .player_spawns
+    .iter()
+    .filter(move |s| **s == p)
+    .map(|s| MapEntity::PlayerSpawn(*s));
+  let spawners = map
+    .spawners
+    .iter()
+    .filter(move |s| s.pos == p)
+    .map(|s| MapEntity::Spawner(s.clone()));
+  let pickups = map
+    .pickups
+    .iter()
+    .filter(move |pickup| pickup.pos == p)
+    .map(|pickup| MapEntity::Pickup(pickup.clone()));
+  spawns.chain(spawners).chain(pickups)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dimensions::WorldCoord as Coord;
+
+  fn p(x: u32, y: u32) -> TilePoint {
+    TilePoint::new(x, y)
+  }
+
+  #[test]
+  fn wont_wall_in_living_units() {
+    let mut map: Map = "3\n1\nOOO\n".parse().unwrap();
+    // A unit standing on the middle tile, just touching the first but not
+    // the last.
+    let unit = WorldRect {
+      top_left: p(1, 0).to_world_point(),
+      width: Coord(60.),
+      height: Coord(60.),
+    };
+    let wall = Tool::Paint(GridTile::Obstacle);
+    assert!(!apply_tool(
+      &mut map,
+      &[unit],
+      wall,
+      MouseButton::Left,
+      p(1, 0)
+    ));
+    assert!(!apply_tool(
+      &mut map,
+      &[unit],
+      wall,
+      MouseButton::Left,
+      p(0, 0)
+    ));
+    assert!(!apply_tool(
+      &mut map,
+      &[unit],
+      Tool::Paint(GridTile::Water),
+      MouseButton::Left,
+      p(1, 0)
+    ));
+    // Mud doesn't block, and nobody's near the last tile.
+    assert!(apply_tool(
+      &mut map,
+      &[unit],
+      Tool::Paint(GridTile::Mud),
+      MouseButton::Left,
+      p(1, 0)
+    ));
+    assert!(apply_tool(
+      &mut map,
+      &[unit],
+      wall,
+      MouseButton::Left,
+      p(2, 0)
+    ));
+    assert_eq!(
+      map.grid_tiles,
+      vec![GridTile::Empty, GridTile::Mud, GridTile::Obstacle]
+    );
+  }
+
+  // A 5x1 map with two player spawns, a spawner and a pickup on the first
+  // four tiles, and a wall on the last.
+  fn map_with_entities() -> Map {
+    "shooter-map 2
+size 5 1
+legend 2
+O floor
+X wall
+tiles
+OOOOX
+entities 4
+player_spawn 0 0
+player_spawn 1 0
+spawner grunt 2 0
+pickup health 3 0
+"
+    .parse()
+    .unwrap()
+  }
+
+  // Paints tile at p the way a click does, with undo.
+  fn paint(editor: &mut Editor, map: &mut Map, tile: GridTile, p: TilePoint) {
+    editor.push_undo(map.clone());
+    assert!(apply_tool(
+      map,
+      &[],
+      Tool::Paint(tile),
+      MouseButton::Left,
+      p
+    ));
+  }
+
+  #[test]
+  fn undoes_and_redoes() {
+    let mut editor = Editor::new("map.txt");
+    let mut map: Map = "3\n1\nOOO\n".parse().unwrap();
+    let original = map.clone();
+    paint(&mut editor, &mut map, GridTile::Obstacle, p(0, 0));
+    paint(&mut editor, &mut map, GridTile::Mud, p(1, 0));
+    let edited = map.clone();
+
+    editor.undo(&mut map);
+    editor.undo(&mut map);
+    assert_eq!(map, original);
+    // Nothing left to undo.
+    editor.undo(&mut map);
+    assert_eq!(map, original);
+
+    editor.redo(&mut map);
+    editor.redo(&mut map);
+    assert_eq!(map, edited);
+    editor.redo(&mut map);
+    assert_eq!(map, edited);
+    assert!(editor.dirty);
+  }
+
+  #[test]
+  fn new_edits_clear_redo() {
+    let mut editor = Editor::new("map.txt");
+    let mut map: Map = "3\n1\nOOO\n".parse().unwrap();
+    paint(&mut editor, &mut map, GridTile::Obstacle, p(0, 0));
+    editor.undo(&mut map);
+    paint(&mut editor, &mut map, GridTile::Mud, p(1, 0));
+    let edited = map.clone();
+
+    editor.redo(&mut map);
+    assert_eq!(map, edited);
+    assert_eq!(map.get_tile(p(0, 0)), Some(GridTile::Empty));
+  }
+
+  #[test]
+  fn forgets_the_oldest_undo() {
+    let mut editor = Editor::new("map.txt");
+    let mut map: Map = "1\n1\nO\n".parse().unwrap();
+    // Each edit widens the map, so they're easy to tell apart.
+    for width in 2..MAX_UNDO as u32 + 3 {
+      editor.resize(&mut map, width, 1);
+    }
+    for _ in 0..MAX_UNDO + 1 {
+      editor.undo(&mut map);
+    }
+    // The first edit can't be undone any more.
+    assert_eq!(map.width, 2);
+  }
+
+  #[test]
+  fn wont_paint_over_entities() {
+    let mut map = map_with_entities();
+    for x in 0..4 {
+      for tile in [GridTile::Obstacle, GridTile::Water] {
+        assert!(
+          !apply_tool(&mut map, &[], Tool::Paint(tile), MouseButton::Left, p(x, 0)),
+          "painted {:?} at {}",
+          tile,
+          x
+        );
+      }
+      assert!(apply_tool(
+        &mut map,
+        &[],
+        Tool::Paint(GridTile::Mud),
+        MouseButton::Left,
+        p(x, 0)
+      ));
+    }
+    assert!(map.entities().all(|e| map.check_entity(&e).is_ok()));
+  }
+
+  #[test]
+  fn places_and_removes_player_spawns() {
+    let mut map = map_with_entities();
+    let spawn = |map: &mut Map, button, x| apply_tool(map, &[], Tool::PlayerSpawn, button, p(x, 0));
+    // Not on a wall, or twice on a tile.
+    assert!(!spawn(&mut map, MouseButton::Left, 4));
+    assert!(!spawn(&mut map, MouseButton::Left, 0));
+    assert!(spawn(&mut map, MouseButton::Left, 2));
+    assert_eq!(map.player_spawns, [p(0, 0), p(1, 0), p(2, 0)]);
+
+    assert!(spawn(&mut map, MouseButton::Right, 0));
+    assert!(!spawn(&mut map, MouseButton::Right, 3));
+    assert_eq!(map.player_spawns, [p(1, 0), p(2, 0)]);
+  }
+
+  #[test]
+  fn keeps_a_spawn_for_every_player() {
+    let mut editor = Editor::new("map.txt");
+    let mut map = map_with_entities();
+    let original = map.clone();
+    assert_eq!(map.player_spawns.len(), game::N_PLAYERS);
+
+    assert!(!apply_tool(
+      &mut map,
+      &[],
+      Tool::PlayerSpawn,
+      MouseButton::Right,
+      p(1, 0)
+    ));
+    // Shrinking would cut off the spawn at (1, 0).
+    editor.resize(&mut map, 1, 1);
+    assert_eq!(map, original);
+    // So there's nothing to undo.
+    assert!(editor.undo.is_empty());
+
+    // A map that's already short of spawns can still be edited, so long as
+    // it doesn't lose any more.
+    let mut map: Map = "3\n2\nOOO\nOOO\n".parse().unwrap();
+    map.player_spawns.push(p(2, 0));
+    editor.resize(&mut map, 3, 1);
+    assert_eq!(map.height, 1);
+    editor.resize(&mut map, 2, 1);
+    assert_eq!(map.width, 3);
+  }
+
+  #[test]
+  fn resizing_drops_what_no_longer_fits() {
+    let mut editor = Editor::new("map.txt");
+    let mut map = map_with_entities();
+    let original = map.clone();
+
+    editor.resize(&mut map, 3, 2);
+    assert_eq!((map.width, map.height), (3, 2));
+    assert_eq!(map.grid_tiles, [GridTile::Empty; 6]);
+    assert_eq!(map.player_spawns, [p(0, 0), p(1, 0)]);
+    assert_eq!(map.spawners.len(), 1);
+    assert!(map.pickups.is_empty());
+
+    editor.resize(&mut map, 6, 1);
+    assert_eq!(map.grid_tiles.len(), 6);
+    assert_eq!(map.get_tile(p(4, 0)), Some(GridTile::Empty));
+
+    // Undo brings back what was cut off.
+    editor.undo(&mut map);
+    editor.undo(&mut map);
+    assert_eq!(map, original);
+  }
+}
@@ -0,0 +1,249 @@
+// Flow fields, for steering lots of enemies towards the players at once.
+//
+// Rather than finding a path for every enemy, each player has a field holding
+// the cost of the best path from every tile to the tile they're on, found with
+// Dijkstra's algorithm. An enemy anywhere on the map can then find its way by
+// stepping to whichever neighboring tile is closest to a player.
+//
+// Fields aren't repaired incrementally. When a player moves onto a different
+// tile, every tile's cost to reach them can change, so their field is worked
+// out again in full, reusing its buffer. While a player stays on one tile,
+// their field costs nothing to keep.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::dimensions::{WorldCoord as Coord, WorldPoint as Point};
+use crate::game::Player;
+use crate::map::{Map, TilePoint, ToTilePoint};
+use crate::pathfinding::{is_open, neighbors, step_cost};
+
+// The cost of getting from each tile to one goal tile.
+pub struct FlowField {
+  // The tile the costs lead to, or None if the field is empty, such as for a
+  // dead player.
+  goal: Option<TilePoint>,
+  // The map size the costs are for.
+  width: u32,
+  height: u32,
+  // By tile index. u32::MAX for tiles with no path to the goal.
+  cost: Vec<u32>,
+}
+
+impl FlowField {
+  pub fn new() -> FlowField {
+    FlowField {
+      goal: None,
+      width: 0,
+      height: 0,
+      cost: vec![],
+    }
+  }
+
+  // Makes the field lead to goal, or empties it if goal is None. The costs
+  // are only worked out again if the goal or the map's size has changed.
+  // Returns whether they were.
+  pub fn update(&mut self, map: &Map, goal: Option<TilePoint>) -> bool {
+    if goal == self.goal && (map.width, map.height) == (self.width, self.height) {
+      return false;
+    }
+    self.goal = goal;
+    self.width = map.width;
+    self.height = map.height;
+    self.cost.clear();
+    self.cost.resize(map.grid_tiles.len(), u32::MAX);
+    let goal = match goal {
+      Some(goal) if is_open(map, goal) => goal,
+      _ => return true,
+    };
+
+    // Dijkstra's, out from the goal. A step can cost more one way than the
+    // other, so each is costed the way an enemy would take it: from next to p.
+    let mut open = BinaryHeap::new();
+    let goal_index = self.index(goal);
+    self.cost[goal_index] = 0;
+    open.push(Reverse((0, goal.y(), goal.x())));
+    while let Some(Reverse((cost, y, x))) = open.pop() {
+      let p = TilePoint::new(x, y);
+      if cost > self.cost[self.index(p)] {
+        continue;
+      }
+      for next in neighbors(map, p) {
+        let next_cost = cost + step_cost(map, next, p);
+        let next_index = self.index(next);
+        if next_cost < self.cost[next_index] {
+          self.cost[next_index] = next_cost;
+          open.push(Reverse((next_cost, next.y(), next.x())));
+        }
+      }
+    }
+    true
+  }
+
+  // Forgets the goal, so that the next update works the costs out again.
+  // Needed when the map's tiles change.
+  pub fn invalidate(&mut self) {
+    self.goal = None;
+    self.cost.clear();
+  }
+
+  // The cost of the best path from p to the goal, or None if there isn't one.
+  pub fn cost_at(&self, p: TilePoint) -> Option<u32> {
+    if p.x() >= self.width || p.y() >= self.height {
+      return None;
+    }
+    match self.cost.get(self.index(p)) {
+      Some(&cost) if cost != u32::MAX => Some(cost),
+      _ => None,
+    }
+  }
+
+  // The neighbor of p to step to, to get closer to the goal. None if p is the
+  // goal, or there's no path from it.
+  pub fn next_step(&self, map: &Map, p: TilePoint) -> Option<TilePoint> {
+    let here = self.cost_at(p)?;
+    neighbors(map, p)
+      .filter_map(|n| self.cost_at(n).map(|cost| (cost, n)))
+      .filter(|(cost, _)| *cost < here)
+      .min_by_key(|(cost, n)| (*cost, n.y(), n.x()))
+      .map(|(_, n)| n)
+  }
+
+  fn index(&self, p: TilePoint) -> usize {
+    (p.x() + p.y() * self.width) as usize
+  }
+}
+
+// A flow field for each player, by player index.
+pub struct FlowFields {
+  pub fields: Vec<FlowField>,
+}
+
+impl FlowFields {
+  pub fn new() -> FlowFields {
+    FlowFields { fields: vec![] }
+  }
+
+  // Points each field at its player's tile. Only fields whose player changed
+  // tiles, or died, are worked out again.
+  pub fn update(&mut self, map: &Map, players: &[Player]) {
+    self.fields.resize_with(players.len(), FlowField::new);
+    for (field, player) in self.fields.iter_mut().zip(players) {
+      let goal = if player.unit.is_alive() {
+        Some(player.unit.pos.to_tile_point())
+      } else {
+        None
+      };
+      field.update(map, goal);
+    }
+  }
+
+  pub fn invalidate(&mut self) {
+    for field in self.fields.iter_mut() {
+      field.invalidate();
+    }
+  }
+
+  // The index of the player with the cheapest path from pos, if any can be
+  // reached.
+  pub fn nearest_player(&self, pos: Point) -> Option<usize> {
+    let tile = pos.to_tile_point();
+    self
+      .fields
+      .iter()
+      .enumerate()
+      .filter_map(|(i, field)| field.cost_at(tile).map(|cost| (cost, i)))
+      .min()
+      .map(|(_, i)| i)
+  }
+
+  // Which way a unit at pos should head to get to the nearest player, as a
+  // unit vector. None if no player can be reached, or the unit is already on
+  // the nearest player's tile and can head straight for them.
+  pub fn direction(&self, map: &Map, pos: Point) -> Option<Point> {
+    let field = &self.fields[self.nearest_player(pos)?];
+    let next = field.next_step(map, pos.to_tile_point())?;
+    let dir = next.center_to_world_point() - pos;
+    if dir.x == Coord(0.) && dir.y == Coord(0.) {
+      return None;
+    }
+    Some(dir.normalized())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::test_map;
+  use crate::pathfinding::{find_path, steps};
+
+  fn field_to(map: &Map, goal: TilePoint) -> FlowField {
+    let mut field = FlowField::new();
+    field.update(map, Some(goal));
+    field
+  }
+
+  #[test]
+  fn costs_match_best_paths() {
+    let map = test_map();
+    let goal = TilePoint::new(9, 7);
+    let field = field_to(&map, goal);
+    for tile in map.tiles() {
+      let path = find_path(&map, tile.pos, goal);
+      let path_cost = path.map(|path| {
+        path
+          .windows(2)
+          .map(|w| steps(&map, w[0]).find(|(n, _)| *n == w[1]).unwrap().1)
+          .sum::<u32>()
+      });
+      assert_eq!(field.cost_at(tile.pos), path_cost, "from {:?}", tile.pos);
+    }
+  }
+
+  #[test]
+  fn steps_lead_to_goal() {
+    let map = test_map();
+    let goal = TilePoint::new(9, 7);
+    let field = field_to(&map, goal);
+    let mut p = TilePoint::new(19, 11);
+    for _ in 0..map.grid_tiles.len() {
+      match field.next_step(&map, p) {
+        Some(next) => p = next,
+        None => break,
+      }
+    }
+    assert_eq!(p, goal);
+  }
+
+  #[test]
+  fn only_updates_when_goal_moves() {
+    let map = test_map();
+    let mut field = field_to(&map, TilePoint::new(1, 1));
+    assert!(!field.update(&map, Some(TilePoint::new(1, 1))));
+    assert!(field.update(&map, Some(TilePoint::new(2, 1))));
+    field.invalidate();
+    assert!(field.update(&map, Some(TilePoint::new(2, 1))));
+    assert!(field.update(&map, None));
+    assert_eq!(field.cost_at(TilePoint::new(2, 1)), None);
+  }
+
+  #[test]
+  fn heads_for_nearest_player() {
+    let map = test_map();
+    let fields = FlowFields {
+      fields: vec![
+        field_to(&map, TilePoint::new(1, 1)),
+        field_to(&map, TilePoint::new(9, 7)),
+      ],
+    };
+    // Just outside the spiral, player 1 is close as the crow flies but the
+    // way in goes all the way round.
+    let outside = TilePoint::new(9, 9).center_to_world_point();
+    assert_eq!(fields.nearest_player(outside), Some(0));
+    let inside = TilePoint::new(11, 6).center_to_world_point();
+    assert_eq!(fields.nearest_player(inside), Some(1));
+    // Straight down the inside of the spiral.
+    let dir = fields.direction(&map, inside).unwrap();
+    assert!(dir.y.0 > 0.99);
+  }
+}
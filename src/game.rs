@@ -1,11 +1,39 @@
-use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
 
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::ai::{self, Brain};
 use crate::dimensions::{WorldCoord as Coord, WorldPoint as Point, WorldRect as Rect};
-use crate::map::Map;
+use crate::flow_field::FlowFields;
+use crate::map::{Map, PickupKind, TilePoint};
 use crate::sprite_sheet::SpriteKey;
 
-const TICKS_PER_SEC: u32 = 120; // TODO: Drop to 24 when fps and tps differ.
-const TICK_TIME: f32 = 1.0 / (TICKS_PER_SEC as f32);
+pub const TICKS_PER_SEC: u32 = 24;
+pub const TICK_TIME: f32 = 1.0 / (TICKS_PER_SEC as f32);
+
+// How long a shooting animation plays before going back to idle or walking.
+const SHOOT_ANIM_TICKS: u32 = TICKS_PER_SEC / 4;
+// How long dead non-player units stick around, so their death animation can
+// play out.
+const CORPSE_TICKS: u32 = TICKS_PER_SEC * 2;
+
+pub const DEFAULT_MAP_PATH: &str = "media/test-map.txt";
+
+// How many players there are. Each needs a player spawn on the map.
+pub const N_PLAYERS: usize = 2;
+
+// How often each spawner spawns an enemy, and how many of its enemies can be
+// alive at once.
+const SPAWN_INTERVAL: GameDur = GameDur {
+  ticks: TICKS_PER_SEC * 5,
+};
+const MAX_ENEMIES_PER_SPAWNER: usize = 3;
+
+// How close a player has to get to a pickup to collect it.
+const PICKUP_RAD: Coord = Coord(12.0);
+// How much a health pickup heals.
+const HEALTH_PICKUP_AMOUNT: u32 = 50;
 
 #[derive(Clone, Copy, PartialEq)]
 pub struct GameDur {
@@ -26,113 +54,359 @@ pub struct State {
   pub players: Vec<Player>,
   pub units: Vec<Unit>,
   pub bullets: Vec<Bullet>,
+  pub pickups: Vec<Pickup>,
+  // Where bullets hit things during the last tick, for effects.
+  pub impacts: Vec<Impact>,
+  // Ticks until each spawner next spawns an enemy, by the spawner's tile.
+  // Spawners without an entry are due to spawn.
+  pub spawn_timers: HashMap<TilePoint, u32>,
 
   pub map: Map,
+  pub stats: Stats,
+  // Which way to go to get to each player, for enemies to chase them. Kept up
+  // to date at the end of every tick.
+  pub flow_fields: FlowFields,
+
+  // All randomness in the simulation must come from here, so that a game can
+  // be reproduced from its seed and inputs.
+  pub rng: StdRng,
 
   pub next_uid: UID,
 }
 
 impl State {
-  pub fn blank() -> State {
+  pub fn blank(map_path: &str, seed: u64) -> Result<State, String> {
+    Ok(State::on_map(Map::from_file(map_path)?, seed))
+  }
+
+  // An empty game on map, with no players yet.
+  pub fn on_map(map: Map, seed: u64) -> State {
     State {
       players: vec![],
       units: vec![],
       bullets: vec![],
+      pickups: vec![],
+      impacts: vec![],
+      spawn_timers: HashMap::new(),
+
+      map,
+      stats: Stats::default(),
+      flow_fields: FlowFields::new(),
 
-      map: Map::from_file("media/test-map.txt").expect("couldn't load the map"),
+      rng: StdRng::seed_from_u64(seed),
 
       next_uid: 0,
     }
   }
 
-  pub fn level1() -> State {
-    let mut state = State::blank();
+  // Starts a game on the map at map_path, with a player at each of the map's
+  // first N_PLAYERS player spawns.
+  pub fn level1(map_path: &str, seed: u64) -> Result<State, String> {
+    let mut state = State::blank(map_path, seed)?;
 
-    let uid = state.next_uid();
-    state.players.push(Player {
-      keys: PlayerKeys {
-        up: Keycode::W,
-        down: Keycode::S,
-        left: Keycode::A,
-        right: Keycode::D,
-      },
-      unit: Unit {
-        uid,
-        sprite_key: "newt_gingrich".to_string(),
+    if state.map.player_spawns.len() < N_PLAYERS {
+      return Err(format!(
+        "map has {} player spawns, but there are {} players",
+        state.map.player_spawns.len(),
+        N_PLAYERS
+      ));
+    }
+    for i in 0..N_PLAYERS {
+      let pos = state.map.player_spawns[i].center_to_world_point();
+      let uid = state.next_uid();
+      state.players.push(Player::new(uid, pos));
+    }
 
-        pos: Point::new(Coord(100.0), Coord(100.0)),
-        heading: Point::new(Coord(1.0), Coord(0.0)),
-        move_dir: Point::new(Coord(0.0), Coord(0.0)),
-        rad: Coord(10.0),
-        base_speed: Coord(300.0),
+    // Catch typos in spawners now, rather than when they first spawn.
+    for spawner in state.map.spawners.iter() {
+      ai::enemy_kind(&spawner.kind)?;
+    }
 
-        shooting: false,
-        ticks_per_shot: TICKS_PER_SEC / 2,
-        ticks_to_shot: 0,
-      },
-    });
-    state.players.push(Player {
-      keys: PlayerKeys {
-        up: Keycode::Up,
-        down: Keycode::Down,
-        left: Keycode::Left,
-        right: Keycode::Right,
-      },
-      unit: Unit {
-        uid,
-        sprite_key: "newt_gingrich".to_string(),
+    state.pickups = state
+      .map
+      .pickups
+      .iter()
+      .map(|p| Pickup {
+        kind: p.kind,
+        pos: p.pos.center_to_world_point(),
+      })
+      .collect();
+    state.flow_fields.update(&state.map, &state.players);
 
-        pos: Point::new(Coord(100.0), Coord(400.0)),
-        heading: Point::new(Coord(1.0), Coord(0.0)),
-        move_dir: Point::new(Coord(0.0), Coord(0.0)),
-        rad: Coord(10.0),
-        base_speed: Coord(300.0),
+    Ok(state)
+  }
 
-        shooting: false,
-        ticks_per_shot: TICKS_PER_SEC / 2,
-        ticks_to_shot: 0,
-      },
-    });
+  // Advances the game by one tick. commands[i] is the input for players[i];
+  // players without a command keep doing whatever they were doing.
+  pub fn tick(&mut self, commands: &[PlayerCommand]) {
+    for (player, command) in self.players.iter_mut().zip(commands.iter()) {
+      command.apply(&mut player.unit);
+    }
 
-    state
-  }
+    // Remember where everything was, so that rendering can interpolate between
+    // the last two ticks.
+    for player in self.players.iter_mut() {
+      player.unit.prev_pos = player.unit.pos;
+    }
+    for unit in self.units.iter_mut() {
+      unit.prev_pos = unit.pos;
+    }
+    for bullet in self.bullets.iter_mut() {
+      bullet.prev_pos = bullet.pos;
+    }
+
+    self.spawn_enemies();
+    for unit in self.units.iter_mut() {
+      ai::think(
+        unit,
+        &self.map,
+        &self.players,
+        &self.flow_fields,
+        &mut self.rng,
+      );
+    }
 
-  pub fn tick(&mut self) {
     for player in self.players.iter_mut() {
-      let vel = player.unit.move_dir.normalized() * Coord(TICK_TIME) * player.unit.base_speed;
-      let new_pos = player.unit.pos + vel;
-      let new_bounds = player.unit.bounding_box_at(new_pos);
-      if !self.map.rect_intersects_wall(new_bounds) {
-        player.unit.pos = player.unit.pos + vel;
+      if !player.unit.is_alive() {
+        continue;
       }
+      player.unit.try_move(&self.map);
 
-      if player.unit.shooting && player.unit.ticks_to_shot == 0 {
-        let heading = player.unit.heading;
-        self.bullets.push(Bullet {
-          pos: player.unit.pos + heading * player.unit.rad * Coord(1.1),
-          heading,
-          rad: Coord(2.0),
-          speed: Coord(500.0),
-          will_die_at_end_of_tick: false,
-        });
-        player.unit.ticks_to_shot = player.unit.ticks_per_shot;
+      let bounds = player.unit.bounding_box();
+      self.pickups.retain(|pickup| {
+        if !pickup.bounding_box().intersects(&bounds) || !pickup.apply(&mut player.unit) {
+          return true;
+        }
+        self.stats.pickups_collected += 1;
+        false
+      });
+    }
+    for unit in self.units.iter_mut() {
+      if unit.is_alive() {
+        unit.try_move(&self.map);
       }
+    }
 
-      if player.unit.ticks_to_shot > 0 {
-        player.unit.ticks_to_shot -= 1;
+    let units = self
+      .players
+      .iter_mut()
+      .map(|p| &mut p.unit)
+      .chain(self.units.iter_mut());
+    for unit in units {
+      if !unit.is_alive() {
+        continue;
+      }
+      if let Some(bullet) = unit.try_shoot() {
+        self.bullets.push(bullet);
+        self.stats.shots_fired += 1;
       }
     }
 
+    // Bullets are swept along the whole of this tick's move, so that fast
+    // ones can't skip over thin walls and units between ticks.
+    self.impacts.clear();
     for bullet in self.bullets.iter_mut() {
-      let vel = bullet.heading * bullet.speed * Coord(TICK_TIME);
-      bullet.pos = bullet.pos + vel;
-      if self.map.rect_intersects_wall(bullet.bounding_box()) {
-        bullet.will_die_at_end_of_tick = true;
-      }
+      let delta = bullet.heading * bullet.speed * Coord(TICK_TIME);
+      let wall_hit = bullet.sweep_walls(&self.map, delta);
+      let unit_hits = self
+        .players
+        .iter()
+        .map(|p| &p.unit)
+        .chain(self.units.iter())
+        .enumerate()
+        .filter(|(_, unit)| bullet.can_hit(unit))
+        .filter_map(|(i, unit)| {
+          let (t, normal) = bullet.sweep_rect(unit.bounding_box(), delta)?;
+          Some((t, normal, Some(i)))
+        });
+      // Walls come first, so a bullet can't hit someone on the far side of a
+      // wall it's touching.
+      let hit = wall_hit
+        .map(|(t, normal)| (t, normal, None))
+        .into_iter()
+        .chain(unit_hits)
+        .min_by(|a, b| a.0.total_cmp(&b.0));
+      let (t, normal, target) = match hit {
+        Some(hit) => hit,
+        None => {
+          // Nothing off the map stops bullets, so once they leave it they'd
+          // fly forever.
+          if !self.map.bounds().intersects(&bullet.swept_box(delta)) {
+            bullet.will_die_at_end_of_tick = true;
+          }
+          bullet.pos = bullet.pos + delta;
+          continue;
+        }
+      };
 
-      // TODO: Check for intersection with player and update health.
+      bullet.pos = bullet.pos + delta * Coord(t);
+      bullet.will_die_at_end_of_tick = true;
+      let mut impact = Impact {
+        pos: bullet.pos - normal * bullet.rad,
+        normal,
+        unit: None,
+      };
+      if let Some(i) = target {
+        let unit = self
+          .players
+          .iter_mut()
+          .map(|p| &mut p.unit)
+          .chain(self.units.iter_mut())
+          .nth(i)
+          .unwrap();
+        let health_before = unit.health;
+        unit.take_damage(bullet.damage);
+        self.stats.hits += 1;
+        self.stats.damage_dealt += health_before - unit.health;
+        impact.unit = Some(unit.uid);
+      }
+      self.impacts.push(impact);
     }
     self.bullets.retain(|b| !b.will_die_at_end_of_tick);
+
+    // Now that we know what everyone did this tick, advance their animations.
+    let units = self
+      .players
+      .iter_mut()
+      .map(|p| &mut p.unit)
+      .chain(self.units.iter_mut());
+    for unit in units {
+      unit.update_anim();
+    }
+
+    // Players stay around when they die so that their slot and bindings are
+    // kept, but other units are cleaned up once their corpse has been seen.
+    self
+      .units
+      .retain(|u| u.is_alive() || u.anim.ticks < CORPSE_TICKS);
+
+    self.flow_fields.update(&self.map, &self.players);
+
+    self.stats.ticks += 1;
+  }
+
+  // Has each spawner spawn an enemy when its timer runs out, unless it already
+  // has as many alive as it's allowed.
+  //
+  // Spawners are told apart by their tiles rather than their order, as
+  // resizing the map in the editor can drop some and shift the rest along.
+  fn spawn_enemies(&mut self) {
+    let mut spawns = vec![];
+    for spawner in self.map.spawners.iter() {
+      let timer = self.spawn_timers.entry(spawner.pos).or_insert(0);
+      if *timer > 0 {
+        *timer -= 1;
+        continue;
+      }
+      let n_alive = self
+        .units
+        .iter()
+        .filter(|u| u.is_alive() && u.brain.as_ref().is_some_and(|b| b.home == spawner.pos))
+        .count();
+      if n_alive >= MAX_ENEMIES_PER_SPAWNER {
+        continue;
+      }
+      let kind = match ai::enemy_kind(&spawner.kind) {
+        Ok(kind) => kind,
+        Err(_) => continue,
+      };
+      *timer = SPAWN_INTERVAL.ticks;
+      spawns.push((kind, spawner.pos));
+    }
+    for (kind, pos) in spawns {
+      let uid = self.next_uid();
+      self.units.push(ai::spawn_enemy(kind, uid, pos));
+    }
+  }
+
+  // The bounding boxes of all the units that are still alive.
+  pub fn living_unit_boxes(&self) -> Vec<Rect> {
+    self
+      .players
+      .iter()
+      .map(|p| &p.unit)
+      .chain(self.units.iter())
+      .filter(|u| u.is_alive())
+      .map(|u| u.bounding_box())
+      .collect()
+  }
+
+  // Puts things back on the map after it's been edited. Living units that are
+  // off it, or stuck in something they can't walk through, are moved to the
+  // nearest tile they can stand on, and bullets off it are dropped.
+  pub fn fit_to_map(&mut self) {
+    let map = &self.map;
+    let bounds = map.bounds();
+    let units = self
+      .players
+      .iter_mut()
+      .map(|p| &mut p.unit)
+      .chain(self.units.iter_mut());
+    for unit in units {
+      if !unit.is_alive() {
+        continue;
+      }
+      let rect = unit.bounding_box();
+      let bot_right = rect.top_left + Point::new(rect.width, rect.height);
+      let fits = bounds.contains(rect.top_left) && bounds.contains(bot_right);
+      if fits && !map.rect_blocks_movement(rect) {
+        continue;
+      }
+      if let Some(tile) = map.nearest_open_tile(unit.pos) {
+        unit.pos = tile.center_to_world_point();
+        unit.prev_pos = unit.pos;
+      }
+    }
+    self
+      .bullets
+      .retain(|b| bounds.intersects(&b.bounding_box()));
+  }
+
+  // All of the sprite keys used by units, so they can be resolved against the
+  // sprite sheet when it's loaded.
+  pub fn sprite_keys_mut(&mut self) -> impl Iterator<Item = &mut SpriteKey> {
+    self
+      .players
+      .iter_mut()
+      .map(|p| &mut p.unit)
+      .chain(self.units.iter_mut())
+      .map(|u| &mut u.sprite_key)
+  }
+
+  // A hash of the simulation state, for detecting desyncs between two runs
+  // that should be identical. Floats are hashed by their bits, so this is
+  // only stable across runs of the same build.
+  pub fn checksum(&self) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.write_u32(self.stats.ticks);
+    hash.write_u32(self.next_uid);
+    let units = self
+      .players
+      .iter()
+      .map(|p| &p.unit)
+      .chain(self.units.iter());
+    for unit in units {
+      hash.write_u32(unit.uid);
+      hash.write_point(unit.pos);
+      hash.write_point(unit.heading);
+      hash.write_u32(unit.health);
+      hash.write_u32(unit.ticks_to_shot);
+      hash.write_u32(unit.anim.action as u32);
+      hash.write_u32(unit.anim.ticks);
+      if let Some(brain) = &unit.brain {
+        hash.write_u32(brain.behavior as u32);
+      }
+    }
+    for bullet in self.bullets.iter() {
+      hash.write_u32(bullet.owner);
+      hash.write_point(bullet.pos);
+      hash.write_point(bullet.heading);
+    }
+    for pickup in self.pickups.iter() {
+      hash.write_u32(pickup.kind as u32);
+      hash.write_point(pickup.pos);
+    }
+    hash.finish()
   }
 
   fn next_uid(&mut self) -> UID {
@@ -146,16 +420,74 @@ impl State {
 }
 
 pub struct Player {
-  pub keys: PlayerKeys,
   pub unit: Unit,
 }
 
-#[derive(Debug)]
-pub struct PlayerKeys {
-  pub up: Keycode,
-  pub down: Keycode,
-  pub left: Keycode,
-  pub right: Keycode,
+impl Player {
+  // A player standing at pos, ready to shoot.
+  pub fn new(uid: UID, pos: Point) -> Player {
+    Player {
+      unit: Unit {
+        uid,
+        sprite_key: SpriteKey::new("newt_gingrich"),
+
+        pos,
+        prev_pos: pos,
+        heading: Point::new(Coord(1.0), Coord(0.0)),
+        move_dir: Point::new(Coord(0.0), Coord(0.0)),
+        rad: Coord(10.0),
+        base_speed: Coord(300.0),
+
+        shooting: false,
+        ticks_per_shot: TICKS_PER_SEC / 2,
+        ticks_to_shot: 0,
+        shot_damage: 25,
+
+        health: 100,
+        max_health: 100,
+
+        anim: UnitAnim::new(),
+        brain: None,
+      },
+    }
+  }
+}
+
+// Everything a player can tell their unit to do on a given tick.
+#[derive(Clone, Copy, Debug)]
+pub struct PlayerCommand {
+  pub move_dir: Point,
+  // None leaves the unit's heading as it was.
+  pub aim_at: Option<Point>,
+  pub shooting: bool,
+}
+
+impl PlayerCommand {
+  pub fn new() -> PlayerCommand {
+    PlayerCommand {
+      move_dir: Point::new(Coord(0.), Coord(0.)),
+      aim_at: None,
+      shooting: false,
+    }
+  }
+
+  fn apply(&self, unit: &mut Unit) {
+    unit.move_dir = self.move_dir;
+    if let Some(target) = self.aim_at {
+      unit.aim_at(target);
+    }
+    unit.shooting = self.shooting;
+  }
+}
+
+// Running totals, mostly for balance experiments.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+  pub ticks: u32,
+  pub shots_fired: u32,
+  pub hits: u32,
+  pub damage_dealt: u32,
+  pub pickups_collected: u32,
 }
 
 pub struct Unit {
@@ -163,6 +495,8 @@ pub struct Unit {
   pub sprite_key: SpriteKey,
 
   pub pos: Point,
+  // Position as of the start of the last tick.
+  pub prev_pos: Point,
   pub heading: Point,
   pub move_dir: Point,
   pub rad: Coord,
@@ -171,6 +505,14 @@ pub struct Unit {
   pub shooting: bool,
   pub ticks_per_shot: u32,
   pub ticks_to_shot: u32,
+  pub shot_damage: u32,
+
+  pub health: u32,
+  pub max_health: u32,
+
+  pub anim: UnitAnim,
+  // What an enemy is thinking. None for players.
+  pub brain: Option<Brain>,
 }
 
 impl Unit {
@@ -185,7 +527,7 @@ impl Unit {
   pub fn bounding_box(&self) -> Rect {
     self.bounding_box_at(self.pos)
   }
-  fn bounding_box_at(&self, p: Point) -> Rect {
+  pub fn bounding_box_at(&self, p: Point) -> Rect {
     let top_left = p - Point::new(self.rad(), self.rad());
     Rect {
       top_left,
@@ -194,10 +536,88 @@ impl Unit {
     }
   }
 
+  // Position between the last two ticks, where alpha=0 is the previous tick
+  // and alpha=1 is the current one.
+  pub fn interpolated_pos(&self, alpha: f32) -> Point {
+    self.prev_pos.lerp(self.pos, alpha)
+  }
+
   pub fn window_rad(&self) -> u32 {
     self.rad().0 as u32
   }
 
+  pub fn is_alive(&self) -> bool {
+    self.health > 0
+  }
+
+  pub fn take_damage(&mut self, damage: u32) {
+    self.health = self.health.saturating_sub(damage);
+  }
+
+  // Picks the animation for what the unit did this tick, and advances it.
+  fn update_anim(&mut self) {
+    let moved = self.pos.x != self.prev_pos.x || self.pos.y != self.prev_pos.y;
+    let action = if !self.is_alive() {
+      UnitAction::Die
+    } else if self.anim.action == UnitAction::Shoot && self.anim.ticks < SHOOT_ANIM_TICKS {
+      UnitAction::Shoot
+    } else if moved {
+      UnitAction::Walk
+    } else {
+      UnitAction::Idle
+    };
+    if action != self.anim.action {
+      self.anim.restart(action);
+    }
+    self.anim.ticks += 1;
+  }
+
+  // Moves the unit one tick along move_dir, at its speed on the ground it's
+  // on. If something's in the way it slides along it where it can.
+  pub fn try_move(&mut self, map: &Map) {
+    let speed = self.speed() * Coord(map.speed_factor_at(self.pos));
+    let vel = self.move_dir.normalized() * Coord(TICK_TIME) * speed;
+    let zero = Coord(0.);
+    for vel in [vel, Point::new(vel.x, zero), Point::new(zero, vel.y)] {
+      if vel.x == zero && vel.y == zero {
+        continue;
+      }
+      let new_pos = self.pos + vel;
+      if !map.rect_blocks_movement(self.bounding_box_at(new_pos)) {
+        self.pos = new_pos;
+        return;
+      }
+    }
+  }
+
+  // Fires a bullet along the unit's heading if it's shooting and ready to,
+  // and counts down to its next shot.
+  fn try_shoot(&mut self) -> Option<Bullet> {
+    let mut bullet = None;
+    if self.shooting && self.ticks_to_shot == 0 {
+      let heading = self.heading;
+      let pos = self.pos + heading * self.rad * Coord(1.1);
+      bullet = Some(Bullet {
+        owner: self.uid,
+        from_enemy: self.brain.is_some(),
+        pos,
+        prev_pos: pos,
+        heading,
+        rad: Coord(2.0),
+        speed: Coord(500.0),
+        damage: self.shot_damage,
+        will_die_at_end_of_tick: false,
+      });
+      self.ticks_to_shot = self.ticks_per_shot;
+      self.anim.restart(UnitAction::Shoot);
+    }
+
+    if self.ticks_to_shot > 0 {
+      self.ticks_to_shot -= 1;
+    }
+    bullet
+  }
+
   pub fn aim_at(&mut self, target: Point) {
     let heading_raw = target - self.pos;
     if heading_raw.x == Coord(0.0) && heading_raw.y == Coord(0.0) {
@@ -208,21 +628,428 @@ impl Unit {
   }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnitAction {
+  Idle,
+  Walk,
+  Shoot,
+  Die,
+}
+
+impl UnitAction {
+  // The suffix of this action's animation in the sprite sheet, eg. a unit with
+  // sprite_key "foo" walks with the animation "foo_walk".
+  pub fn anim_suffix(self) -> &'static str {
+    match self {
+      UnitAction::Idle => "idle",
+      UnitAction::Walk => "walk",
+      UnitAction::Shoot => "shoot",
+      UnitAction::Die => "die",
+    }
+  }
+}
+
+// Which animation a unit is playing, and for how long it's been playing.
+#[derive(Clone, Copy, Debug)]
+pub struct UnitAnim {
+  pub action: UnitAction,
+  pub ticks: u32,
+}
+
+impl UnitAnim {
+  pub fn new() -> UnitAnim {
+    UnitAnim {
+      action: UnitAction::Idle,
+      ticks: 0,
+    }
+  }
+
+  pub fn restart(&mut self, action: UnitAction) {
+    self.action = action;
+    self.ticks = 0;
+  }
+
+  // Time since the animation started, in milliseconds. tick_alpha is as for
+  // Unit::interpolated_pos.
+  pub fn elapsed_ms(&self, tick_alpha: f32) -> u32 {
+    ((self.ticks as f32 + tick_alpha - 1.).max(0.) * TICK_TIME * 1000.) as u32
+  }
+}
+
 pub struct Bullet {
+  // UID of the unit that fired this bullet.
+  pub owner: UID,
+  // Enemies' bullets go through other enemies.
+  pub from_enemy: bool,
   pub pos: Point,
+  // Position as of the start of the last tick.
+  pub prev_pos: Point,
   pub heading: Point,
   pub speed: Coord,
   pub rad: Coord,
+  pub damage: u32,
 
   pub will_die_at_end_of_tick: bool,
 }
 
 impl Bullet {
   pub fn bounding_box(&self) -> Rect {
+    self.bounding_box_at(self.pos)
+  }
+  pub fn bounding_box_at(&self, p: Point) -> Rect {
     Rect {
-      top_left: Point::new(self.pos.x - self.rad, self.pos.y - self.rad),
+      top_left: Point::new(p.x - self.rad, p.y - self.rad),
       width: self.rad * Coord(2.),
       height: self.rad * Coord(2.),
     }
   }
+
+  // See Unit::interpolated_pos.
+  pub fn interpolated_pos(&self, alpha: f32) -> Point {
+    self.prev_pos.lerp(self.pos, alpha)
+  }
+
+  // Bullets can't hit their shooter, or units that are already dead, and
+  // enemies don't shoot each other.
+  fn can_hit(&self, unit: &Unit) -> bool {
+    unit.uid != self.owner && unit.is_alive() && !(self.from_enemy && unit.brain.is_some())
+  }
+
+  // When the bullet would first touch rect if it moved by delta, as for
+  // WorldRect::sweep_point.
+  fn sweep_rect(&self, rect: Rect, delta: Point) -> Option<(f32, Point)> {
+    // Moving the bullet's box against rect is the same as moving its center
+    // against rect grown by the bullet's radius.
+    let grown = Rect {
+      top_left: rect.top_left - Point::new(self.rad, self.rad),
+      width: rect.width + self.rad * Coord(2.),
+      height: rect.height + self.rad * Coord(2.),
+    };
+    grown.sweep_point(self.pos, delta)
+  }
+
+  // The box covering everywhere the bullet goes if it moves by delta.
+  fn swept_box(&self, delta: Point) -> Rect {
+    let start = self.bounding_box();
+    let end = self.bounding_box_at(self.pos + delta);
+    let top_left = Point::new(
+      Coord(start.top_left.x.0.min(end.top_left.x.0)),
+      Coord(start.top_left.y.0.min(end.top_left.y.0)),
+    );
+    Rect {
+      top_left,
+      width: Coord(delta.x.0.abs()) + start.width,
+      height: Coord(delta.y.0.abs()) + start.height,
+    }
+  }
+
+  // When the bullet would first touch a tile that stops bullets if it moved by
+  // delta.
+  fn sweep_walls(&self, map: &Map, delta: Point) -> Option<(f32, Point)> {
+    map
+      .tiles_overlapping_rect(self.swept_box(delta))
+      .filter(|tile| tile.tile.blocks_bullets())
+      .filter_map(|tile| self.sweep_rect(tile.pos.to_world_rect(), delta))
+      .min_by(|a, b| a.0.total_cmp(&b.0))
+  }
+}
+
+// Where a bullet hit a wall or a unit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Impact {
+  // The point on the surface that was hit.
+  pub pos: Point,
+  // Points out of the surface that was hit, towards where the bullet came
+  // from.
+  pub normal: Point,
+  // The unit that was hit, or None for a wall.
+  pub unit: Option<UID>,
+}
+
+// Something lying on the map for players to collect.
+pub struct Pickup {
+  pub kind: PickupKind,
+  pub pos: Point,
+}
+
+impl Pickup {
+  pub fn bounding_box(&self) -> Rect {
+    Rect {
+      top_left: Point::new(self.pos.x - PICKUP_RAD, self.pos.y - PICKUP_RAD),
+      width: PICKUP_RAD * Coord(2.),
+      height: PICKUP_RAD * Coord(2.),
+    }
+  }
+
+  // Gives the pickup to unit. Returns false, leaving the pickup where it is,
+  // if the unit doesn't need it.
+  fn apply(&self, unit: &mut Unit) -> bool {
+    match self.kind {
+      PickupKind::Health => {
+        if unit.health >= unit.max_health {
+          return false;
+        }
+        unit.health = u32::min(unit.health + HEALTH_PICKUP_AMOUNT, unit.max_health);
+        true
+      }
+    }
+  }
+}
+
+// 64-bit FNV-1a. Not cryptographic, but cheap and stable across platforms,
+// unlike std's DefaultHasher.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+  fn new() -> Fnv1a {
+    Fnv1a(0xcbf29ce484222325)
+  }
+
+  fn write_u32(&mut self, n: u32) {
+    for byte in n.to_le_bytes() {
+      self.0 ^= byte as u64;
+      self.0 = self.0.wrapping_mul(0x100000001b3);
+    }
+  }
+
+  fn write_point(&mut self, p: Point) {
+    self.write_u32(p.x.0.to_bits());
+    self.write_u32(p.y.0.to_bits());
+  }
+
+  fn finish(&self) -> u64 {
+    self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::GridTile;
+
+  fn state_with_map(map: &str) -> State {
+    State::on_map(map.parse().unwrap(), 0)
+  }
+
+  fn pt(x: f32, y: f32) -> Point {
+    Point::new(Coord(x), Coord(y))
+  }
+
+  // A bullet at pos heading right, fast enough to go most of the way across
+  // a 64px tile each tick.
+  fn fire_right(state: &mut State, pos: Point, speed: f32) {
+    state.bullets.push(Bullet {
+      owner: 1000,
+      from_enemy: false,
+      pos,
+      prev_pos: pos,
+      heading: pt(1., 0.),
+      speed: Coord(speed),
+      rad: Coord(2.0),
+      damage: 10,
+      will_die_at_end_of_tick: false,
+    });
+  }
+
+  // A player standing in the middle of the tile at (x, y), ready to shoot.
+  fn add_player(state: &mut State, x: u32, y: u32) -> UID {
+    let uid = state.next_uid();
+    let pos = TilePoint::new(x, y).center_to_world_point();
+    state.players.push(Player::new(uid, pos));
+    uid
+  }
+
+  #[test]
+  fn bullets_damage_other_units() {
+    let mut state = state_with_map("4\n1\nOOOO\n");
+    add_player(&mut state, 0, 0);
+    add_player(&mut state, 2, 0);
+    let health = state.players[1].unit.health;
+    let command = PlayerCommand {
+      move_dir: pt(0., 0.),
+      aim_at: Some(state.players[1].unit.pos),
+      shooting: true,
+    };
+    state.tick(&[command]);
+    assert_eq!(state.bullets.len(), 1);
+    // The bullet has 128px to go, at about 21px a tick.
+    for _ in 0..10 {
+      state.tick(&[]);
+    }
+    let damage = state.players[0].unit.shot_damage;
+    assert_eq!(state.players[1].unit.health, health - damage);
+    assert_eq!(state.stats.hits, 1);
+    assert_eq!(state.stats.damage_dealt, damage);
+    assert!(state.bullets.is_empty());
+  }
+
+  #[test]
+  fn bullets_never_hit_their_owner() {
+    let mut state = state_with_map("4\n1\nOOOO\n");
+    let uid = add_player(&mut state, 1, 0);
+    let health = state.players[0].unit.health;
+    // Starting right on top of the shooter.
+    let pos = state.players[0].unit.pos;
+    fire_right(&mut state, pos, 100.);
+    state.bullets[0].owner = uid;
+    state.tick(&[]);
+    assert_eq!(state.players[0].unit.health, health);
+    assert!(state.impacts.is_empty());
+    assert_eq!(state.bullets.len(), 1);
+  }
+
+  #[test]
+  fn health_stops_at_zero_and_bullets_pass_corpses() {
+    let mut state = state_with_map("4\n1\nOOOO\n");
+    add_player(&mut state, 1, 0);
+    let unit = &mut state.players[0].unit;
+    unit.take_damage(unit.health - 1);
+    assert!(unit.is_alive());
+    unit.take_damage(1000);
+    assert_eq!(unit.health, 0);
+    assert!(!unit.is_alive());
+
+    fire_right(&mut state, pt(20., 32.), 100. * TICKS_PER_SEC as f32);
+    state.tick(&[]);
+    assert!(state.impacts.is_empty());
+    assert_eq!(state.stats.hits, 0);
+  }
+
+  #[test]
+  fn fast_bullets_stop_at_walls() {
+    // A wall from x = 64 to 128, and a bullet that would cover 200px in a
+    // tick, ending up past it.
+    let mut state = state_with_map("4\n1\nOXOO\n");
+    fire_right(&mut state, pt(20., 32.), 200. * TICKS_PER_SEC as f32);
+    state.tick(&[]);
+    assert!(state.bullets.is_empty());
+    assert_eq!(
+      state.impacts,
+      vec![Impact {
+        pos: pt(64., 32.),
+        normal: pt(-1., 0.),
+        unit: None,
+      }]
+    );
+  }
+
+  #[test]
+  fn fast_bullets_hit_thin_units() {
+    let mut state = state_with_map("4\n1\nOOOO\n");
+    let mut unit = ai::spawn_enemy(ai::enemy_kind("grunt").unwrap(), 7, TilePoint::new(1, 0));
+    unit.rad = Coord(2.);
+    let health = unit.health;
+    state.units.push(unit);
+    fire_right(&mut state, pt(20., 32.), 200. * TICKS_PER_SEC as f32);
+    state.tick(&[]);
+    assert!(state.bullets.is_empty());
+    // The unit's center is at x = 96.
+    assert_eq!(
+      state.impacts,
+      vec![Impact {
+        pos: pt(94., 32.),
+        normal: pt(-1., 0.),
+        unit: Some(7),
+      }]
+    );
+    assert_eq!(state.units[0].health, health - 10);
+  }
+
+  #[test]
+  fn bullets_stop_at_walls_before_units() {
+    let mut state = state_with_map("4\n1\nOXOO\n");
+    let unit = ai::spawn_enemy(ai::enemy_kind("grunt").unwrap(), 7, TilePoint::new(2, 0));
+    let health = unit.health;
+    state.units.push(unit);
+    fire_right(&mut state, pt(20., 32.), 300. * TICKS_PER_SEC as f32);
+    state.tick(&[]);
+    assert_eq!(state.impacts.len(), 1);
+    assert_eq!(state.impacts[0].unit, None);
+    assert_eq!(state.units[0].health, health);
+  }
+
+  #[test]
+  fn bullets_leaving_the_map_are_dropped() {
+    // The map is 128px wide, and the bullet moves 100px a tick.
+    let mut state = state_with_map("2\n1\nOO\n");
+    fire_right(&mut state, pt(20., 32.), 100. * TICKS_PER_SEC as f32);
+    state.tick(&[]);
+    state.tick(&[]);
+    // It's past the edge now, but was still on the map at the start of the
+    // tick.
+    assert_eq!(state.bullets.len(), 1);
+    state.tick(&[]);
+    assert!(state.bullets.is_empty());
+    assert!(state.impacts.is_empty());
+  }
+
+  #[test]
+  fn spawners_keep_their_enemies_when_others_go() {
+    let mut state = state_with_map(
+      "shooter-map 2\nsize 4 1\nlegend 1\nO floor\ntiles\nOOOO\nentities 2\n\
+       spawner grunt 0 0\nspawner grunt 3 0\n",
+    );
+    let alive_from = |state: &State, home: TilePoint| {
+      state
+        .units
+        .iter()
+        .filter(|u| u.is_alive() && u.brain.as_ref().unwrap().home == home)
+        .count()
+    };
+    let (first, second) = (TilePoint::new(0, 0), TilePoint::new(3, 0));
+    for _ in 0..(SPAWN_INTERVAL.ticks + 1) * MAX_ENEMIES_PER_SPAWNER as u32 {
+      state.tick(&[]);
+    }
+    assert_eq!(alive_from(&state, first), MAX_ENEMIES_PER_SPAWNER);
+    assert_eq!(alive_from(&state, second), MAX_ENEMIES_PER_SPAWNER);
+
+    // Kill off the first spawner's enemies and take it away, as shrinking the
+    // map in the editor would. The second is still full, so doesn't spawn.
+    for unit in state.units.iter_mut() {
+      if unit.brain.as_ref().unwrap().home == first {
+        unit.health = 0;
+      }
+    }
+    state.map.spawners.remove(0);
+    for _ in 0..SPAWN_INTERVAL.ticks + 1 {
+      state.tick(&[]);
+    }
+    assert_eq!(alive_from(&state, first), 0);
+    assert_eq!(alive_from(&state, second), MAX_ENEMIES_PER_SPAWNER);
+  }
+
+  #[test]
+  fn fits_units_back_on_edited_map() {
+    let mut state = state_with_map("4\n2\nOOOO\nOOOO\n");
+    for (uid, x) in [(1, 1), (2, 3)] {
+      let grunt = ai::enemy_kind("grunt").unwrap();
+      state
+        .units
+        .push(ai::spawn_enemy(grunt, uid, TilePoint::new(x, 0)));
+    }
+    fire_right(&mut state, pt(200., 32.), 10.);
+
+    // Wall in the first, and cut the second and the bullet off the map.
+    state.map.set_tile(TilePoint::new(1, 0), GridTile::Obstacle);
+    state.map.resize(3, 2);
+    state.fit_to_map();
+    assert_eq!(
+      state.units[0].pos,
+      TilePoint::new(0, 0).center_to_world_point()
+    );
+    assert_eq!(
+      state.units[1].pos,
+      TilePoint::new(2, 0).center_to_world_point()
+    );
+    assert!(state.bullets.is_empty());
+  }
+
+  #[test]
+  fn slow_bullets_keep_flying() {
+    let mut state = state_with_map("4\n1\nOXOO\n");
+    fire_right(&mut state, pt(20., 32.), 10. * TICKS_PER_SEC as f32);
+    state.tick(&[]);
+    assert!(state.impacts.is_empty());
+    assert_eq!(state.bullets[0].pos, pt(30., 32.));
+  }
 }
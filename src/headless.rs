@@ -0,0 +1,289 @@
+// Runs the simulation without a window, driven by a script of inputs.
+//
+// SDL is never initialized, so this works on machines with no display, but
+// the binary still needs the SDL libraries to start.
+
+use std::str::FromStr;
+
+use crate::camera::Camera;
+use crate::dimensions::{WorldCoord as Coord, WorldPoint as Point};
+use crate::game::{self, PlayerCommand};
+use crate::input::InputSource;
+
+pub fn run(n_ticks: u32, script_path: &str) -> Result<(), String> {
+  let script = Script::from_file(script_path)?;
+  let mut game = game::State::level1(game::DEFAULT_MAP_PATH, 0)?;
+  let mut inputs = script.into_inputs(game.players.len())?;
+
+  // There's no window, so the camera doesn't matter.
+  let camera = Camera::new(0, 0);
+  for _ in 0..n_ticks {
+    let commands: Vec<_> = inputs
+      .iter_mut()
+      .enumerate()
+      .map(|(i, input)| input.command(&game, i, &camera))
+      .collect();
+    game.tick(&commands);
+  }
+
+  print_summary(&game);
+  Ok(())
+}
+
+pub fn print_summary(game: &game::State) {
+  let stats = game.stats;
+  println!(
+    "ran {} ticks ({:.2}s of game time)",
+    stats.ticks,
+    stats.ticks as f32 * game::TICK_TIME
+  );
+  for (i, player) in game.players.iter().enumerate() {
+    let unit = &player.unit;
+    println!(
+      "player {}: {}, health {}/{}, pos ({:.1}, {:.1})",
+      i,
+      if unit.is_alive() { "alive" } else { "dead" },
+      unit.health,
+      unit.max_health,
+      unit.pos.x.0,
+      unit.pos.y.0
+    );
+  }
+  let units_alive = game.units.iter().filter(|u| u.is_alive()).count();
+  println!("units alive: {}", units_alive);
+  println!("bullets in flight: {}", game.bullets.len());
+  println!(
+    "shots fired: {}, hits: {}, damage dealt: {}",
+    stats.shots_fired, stats.hits, stats.damage_dealt
+  );
+  println!("pickups collected: {}", stats.pickups_collected);
+}
+
+// A list of inputs to feed into the game, sorted by tick.
+struct Script {
+  actions: Vec<ScriptAction>,
+}
+
+impl Script {
+  fn from_file(path: &str) -> Result<Script, String> {
+    let file = std::fs::read_to_string(path).map_err(|e| format!("err reading file: {:?}", e))?;
+    file.parse().map_err(|e| format!("{}: {}", path, e))
+  }
+
+  // Splits the script up into one input source per player.
+  fn into_inputs(self, n_players: usize) -> Result<Vec<ScriptedInput>, String> {
+    let mut inputs: Vec<_> = (0..n_players)
+      .map(|_| ScriptedInput {
+        actions: vec![],
+        next_action: 0,
+        command: PlayerCommand::new(),
+      })
+      .collect();
+    for action in self.actions {
+      let input = inputs.get_mut(action.player).ok_or(format!(
+        "tick {}: no player {} (there are {})",
+        action.tick, action.player, n_players
+      ))?;
+      input.actions.push(action);
+    }
+    Ok(inputs)
+  }
+}
+
+impl FromStr for Script {
+  type Err = String;
+
+  // Parses a script.
+  //
+  // Each line has the form "TICK PLAYER ACTION ARGS...", where ACTION is one
+  // of:
+  //   move DX DY  -- set the player's movement direction
+  //   aim X Y     -- aim at the world point (X, Y)
+  //   fire on|off -- start or stop shooting
+  //
+  // Actions are applied just before their tick runs, and stay in effect until
+  // changed. Blank lines and lines starting with # are ignored.
+  fn from_str(file: &str) -> Result<Self, Self::Err> {
+    let mut actions = vec![];
+    for (i, line) in file.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let action: ScriptAction = line.parse().map_err(|e| format!("line {}: {}", i + 1, e))?;
+      actions.push(action);
+    }
+    // Stable, so actions on the same tick keep their file order.
+    actions.sort_by_key(|a| a.tick);
+    Ok(Script { actions })
+  }
+}
+
+// Replays one player's part of a script.
+struct ScriptedInput {
+  // Sorted by tick.
+  actions: Vec<ScriptAction>,
+  next_action: usize,
+  command: PlayerCommand,
+}
+
+impl InputSource for ScriptedInput {
+  fn command(&mut self, game: &game::State, _player: usize, _camera: &Camera) -> PlayerCommand {
+    let tick = game.stats.ticks;
+    while let Some(action) = self.actions.get(self.next_action) {
+      if action.tick > tick {
+        break;
+      }
+      action.apply(&mut self.command);
+      self.next_action += 1;
+    }
+    self.command
+  }
+}
+
+struct ScriptAction {
+  tick: u32,
+  player: usize,
+  kind: ScriptActionKind,
+}
+
+enum ScriptActionKind {
+  Move(Point),
+  Aim(Point),
+  Fire(bool),
+}
+
+impl ScriptAction {
+  fn apply(&self, command: &mut PlayerCommand) {
+    match self.kind {
+      ScriptActionKind::Move(dir) => command.move_dir = dir,
+      ScriptActionKind::Aim(target) => command.aim_at = Some(target),
+      ScriptActionKind::Fire(shooting) => command.shooting = shooting,
+    }
+  }
+}
+
+impl FromStr for ScriptAction {
+  type Err = String;
+
+  // Parses a line of the form "TICK PLAYER ACTION ARGS...".
+  // See Script::from_str for the details.
+  fn from_str(line: &str) -> Result<Self, Self::Err> {
+    let elts: Vec<_> = line.split_whitespace().collect();
+    if elts.len() < 3 {
+      return Err("expected TICK PLAYER ACTION".to_string());
+    }
+    let tick = u32::from_str(elts[0]).map_err(|e| format!("bad TICK: {:?}", e))?;
+    let player = usize::from_str(elts[1]).map_err(|e| format!("bad PLAYER: {:?}", e))?;
+    let args = &elts[3..];
+    let kind = match elts[2] {
+      "move" => ScriptActionKind::Move(parse_point(args)?),
+      "aim" => ScriptActionKind::Aim(parse_point(args)?),
+      "fire" => match args {
+        ["on"] => ScriptActionKind::Fire(true),
+        ["off"] => ScriptActionKind::Fire(false),
+        _ => return Err("fire takes one argument, on or off".to_string()),
+      },
+      other => return Err(format!("unknown action \"{}\"", other)),
+    };
+    Ok(ScriptAction { tick, player, kind })
+  }
+}
+
+fn parse_point(args: &[&str]) -> Result<Point, String> {
+  if args.len() != 2 {
+    return Err(format!("expected 2 numbers, got {}", args.len()));
+  }
+  let x = f32::from_str(args[0]).map_err(|e| format!("{:?}", e))?;
+  let y = f32::from_str(args[1]).map_err(|e| format!("{:?}", e))?;
+  Ok(Point::new(Coord(x), Coord(y)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::test_map;
+
+  fn parse_err(script: &str) -> String {
+    script.parse::<Script>().err().unwrap()
+  }
+
+  fn pt(x: f32, y: f32) -> Point {
+    Point::new(Coord(x), Coord(y))
+  }
+
+  #[test]
+  fn rejects_bad_lines() {
+    assert_eq!(parse_err("0 0"), "line 1: expected TICK PLAYER ACTION");
+    assert_eq!(
+      parse_err("# comment\n\n0 0 jump"),
+      "line 3: unknown action \"jump\""
+    );
+    assert_eq!(parse_err("0 0 move 1"), "line 1: expected 2 numbers, got 1");
+    assert_eq!(
+      parse_err("0 0 fire maybe"),
+      "line 1: fire takes one argument, on or off"
+    );
+    assert!(parse_err("0 x fire on").starts_with("line 1: bad PLAYER"));
+    assert!(parse_err("0 0 aim 1 y").starts_with("line 1: "));
+  }
+
+  #[test]
+  fn rejects_ticks_out_of_range() {
+    assert!(parse_err("-1 0 fire on").starts_with("line 1: bad TICK"));
+    assert!(parse_err("4294967296 0 fire on").starts_with("line 1: bad TICK"));
+    assert!("4294967295 0 fire on".parse::<Script>().is_ok());
+  }
+
+  #[test]
+  fn rejects_missing_players() {
+    let script: Script = "5 2 fire on".parse().unwrap();
+    assert_eq!(
+      script.into_inputs(2).err().unwrap(),
+      "tick 5: no player 2 (there are 2)"
+    );
+  }
+
+  #[test]
+  fn holds_commands_until_changed() {
+    let script: Script = "
+      # Out of order, to check they get sorted.
+      10 0 fire off
+      0 0 move 1 0
+      0 0 fire on
+      5 1 aim 10 20
+      5 0 move 0 1
+    "
+    .parse()
+    .unwrap();
+    let mut inputs = script.into_inputs(2).unwrap();
+    let mut game = game::State::on_map(test_map(), 0);
+    let camera = Camera::new(0, 0);
+
+    let mut commands_at = |game: &mut game::State, tick| {
+      game.stats.ticks = tick;
+      inputs
+        .iter_mut()
+        .enumerate()
+        .map(|(i, input)| input.command(game, i, &camera))
+        .collect::<Vec<_>>()
+    };
+
+    let c = commands_at(&mut game, 0);
+    assert_eq!(c[0].move_dir, pt(1., 0.));
+    assert!(c[0].shooting);
+    assert!(c[1].aim_at.is_none());
+
+    // Nothing new until tick 5, so the same again.
+    let c = commands_at(&mut game, 4);
+    assert_eq!(c[0].move_dir, pt(1., 0.));
+    assert!(c[0].shooting);
+
+    // Skipping ticks still applies everything due.
+    let c = commands_at(&mut game, 12);
+    assert_eq!(c[0].move_dir, pt(0., 1.));
+    assert!(!c[0].shooting);
+    assert_eq!(c[1].aim_at, Some(pt(10., 20.)));
+    assert_eq!(c[1].move_dir, pt(0., 0.));
+  }
+}
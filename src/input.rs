@@ -0,0 +1,312 @@
+// Input sources turn raw input into a PlayerCommand for one player per tick.
+//
+// Each game::Player is driven by exactly one InputSource, so two players never
+// fight over the same keys or mouse.
+
+use std::collections::HashSet;
+
+use sdl2::controller::{Axis, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+
+use crate::camera::Camera;
+use crate::dimensions::{ToWorld, WindowPoint, WorldCoord as Coord, WorldPoint as Point};
+use crate::game::{self, PlayerCommand};
+
+// How far ahead of a keyboard-only or gamepad player their aim point is.
+const AIM_DIST: Coord = Coord(100.);
+
+// Stick deflections smaller than this are treated as centered, since sticks
+// rarely rest at exactly zero.
+const STICK_DEAD_ZONE: i16 = 8000;
+// How far the trigger has to be pulled to shoot.
+const TRIGGER_THRESHOLD: i16 = i16::MAX / 2;
+
+pub trait InputSource {
+  // Observes an input event. Sources should ignore events that aren't theirs.
+  fn handle_event(&mut self, _event: &Event) {}
+
+  // Produces the command for game.players[player] on the next tick.
+  fn command(&mut self, game: &game::State, player: usize, camera: &Camera) -> PlayerCommand;
+}
+
+// All of the inputs bound to one player.
+//
+// A gamepad takes over from the player's usual source while it's plugged in,
+// and hands control back when it's unplugged.
+pub struct PlayerInput {
+  pub source: Box<dyn InputSource>,
+  pub gamepad: Option<GamepadInput>,
+}
+
+impl PlayerInput {
+  pub fn new(source: Box<dyn InputSource>) -> PlayerInput {
+    PlayerInput {
+      source,
+      gamepad: None,
+    }
+  }
+
+  pub fn gamepad_id(&self) -> Option<u32> {
+    self.gamepad.as_ref().map(|g| g.instance_id())
+  }
+}
+
+impl InputSource for PlayerInput {
+  fn handle_event(&mut self, event: &Event) {
+    // Keep the usual source up to date even while the gamepad is in control,
+    // so that nothing is stuck down when it takes back over.
+    self.source.handle_event(event);
+    if let Some(gamepad) = &mut self.gamepad {
+      gamepad.handle_event(event);
+    }
+  }
+
+  fn command(&mut self, game: &game::State, player: usize, camera: &Camera) -> PlayerCommand {
+    match &mut self.gamepad {
+      Some(gamepad) => gamepad.command(game, player, camera),
+      None => self.source.command(game, player, camera),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct PlayerKeys {
+  pub up: Keycode,
+  pub down: Keycode,
+  pub left: Keycode,
+  pub right: Keycode,
+}
+
+impl PlayerKeys {
+  pub fn wasd() -> PlayerKeys {
+    PlayerKeys {
+      up: Keycode::W,
+      down: Keycode::S,
+      left: Keycode::A,
+      right: Keycode::D,
+    }
+  }
+
+  pub fn arrows() -> PlayerKeys {
+    PlayerKeys {
+      up: Keycode::Up,
+      down: Keycode::Down,
+      left: Keycode::Left,
+      right: Keycode::Right,
+    }
+  }
+
+  fn contains(&self, keycode: Keycode) -> bool {
+    [self.up, self.down, self.left, self.right].contains(&keycode)
+  }
+}
+
+// Tracks which of a player's movement keys are held down.
+//
+// Movement is computed from the set of held keys rather than by adding and
+// subtracting on key down / up, so a missed key up (eg. if the window loses
+// focus mid-press) can't leave the player drifting.
+struct HeldKeys {
+  keys: PlayerKeys,
+  held: HashSet<Keycode>,
+}
+
+impl HeldKeys {
+  fn new(keys: PlayerKeys) -> HeldKeys {
+    HeldKeys {
+      keys,
+      held: HashSet::new(),
+    }
+  }
+
+  fn handle_event(&mut self, event: &Event) {
+    match event {
+      Event::KeyDown {
+        keycode: Some(keycode),
+        ..
+      } if self.keys.contains(*keycode) => {
+        self.held.insert(*keycode);
+      }
+      Event::KeyUp {
+        keycode: Some(keycode),
+        ..
+      } => {
+        self.held.remove(keycode);
+      }
+      Event::Window {
+        win_event: WindowEvent::FocusLost,
+        ..
+      } => {
+        self.held.clear();
+      }
+      _ => {}
+    }
+  }
+
+  fn move_dir(&self) -> Point {
+    let axis = |neg: Keycode, pos: Keycode| {
+      let mut v = 0.;
+      if self.held.contains(&neg) {
+        v -= 1.;
+      }
+      if self.held.contains(&pos) {
+        v += 1.;
+      }
+      Coord(v)
+    };
+    Point::new(
+      axis(self.keys.left, self.keys.right),
+      axis(self.keys.up, self.keys.down),
+    )
+  }
+}
+
+// Moves with keys, and aims and shoots with the mouse.
+pub struct KeyboardMouseInput {
+  keys: HeldKeys,
+  // None until the mouse first moves.
+  mouse_pos: Option<WindowPoint>,
+  mouse_down: bool,
+}
+
+impl KeyboardMouseInput {
+  pub fn new(keys: PlayerKeys) -> KeyboardMouseInput {
+    KeyboardMouseInput {
+      keys: HeldKeys::new(keys),
+      mouse_pos: None,
+      mouse_down: false,
+    }
+  }
+}
+
+impl InputSource for KeyboardMouseInput {
+  fn handle_event(&mut self, event: &Event) {
+    self.keys.handle_event(event);
+    match event {
+      Event::MouseButtonDown {
+        mouse_btn: MouseButton::Left,
+        ..
+      } => self.mouse_down = true,
+      Event::MouseButtonUp {
+        mouse_btn: MouseButton::Left,
+        ..
+      } => self.mouse_down = false,
+      Event::MouseMotion { x, y, .. } => self.mouse_pos = Some(WindowPoint::new(*x, *y)),
+      Event::Window {
+        win_event: WindowEvent::FocusLost,
+        ..
+      } => self.mouse_down = false,
+      _ => {}
+    }
+  }
+
+  fn command(&mut self, _game: &game::State, _player: usize, camera: &Camera) -> PlayerCommand {
+    PlayerCommand {
+      move_dir: self.keys.move_dir(),
+      // The mouse is converted to world space every tick, rather than when it
+      // moves, so that aim stays put on the map when the camera moves.
+      aim_at: self.mouse_pos.map(|p| p.to_world(camera)),
+      shooting: self.mouse_down,
+    }
+  }
+}
+
+// Moves with keys and shoots with a fire key, aiming wherever it last moved.
+pub struct KeyboardInput {
+  keys: HeldKeys,
+  fire: Keycode,
+  fire_down: bool,
+}
+
+impl KeyboardInput {
+  pub fn new(keys: PlayerKeys, fire: Keycode) -> KeyboardInput {
+    KeyboardInput {
+      keys: HeldKeys::new(keys),
+      fire,
+      fire_down: false,
+    }
+  }
+}
+
+impl InputSource for KeyboardInput {
+  fn handle_event(&mut self, event: &Event) {
+    self.keys.handle_event(event);
+    match event {
+      Event::KeyDown {
+        keycode: Some(keycode),
+        ..
+      } if *keycode == self.fire => self.fire_down = true,
+      Event::KeyUp {
+        keycode: Some(keycode),
+        ..
+      } if *keycode == self.fire => self.fire_down = false,
+      Event::Window {
+        win_event: WindowEvent::FocusLost,
+        ..
+      } => self.fire_down = false,
+      _ => {}
+    }
+  }
+
+  fn command(&mut self, game: &game::State, player: usize, _camera: &Camera) -> PlayerCommand {
+    let move_dir = self.keys.move_dir();
+    let is_moving = move_dir.x != Coord(0.) || move_dir.y != Coord(0.);
+    let aim_at = match game.players.get(player) {
+      Some(p) if is_moving => Some(p.unit.pos + move_dir.normalized() * AIM_DIST),
+      _ => None,
+    };
+    PlayerCommand {
+      move_dir,
+      aim_at,
+      shooting: self.fire_down,
+    }
+  }
+}
+
+// Moves with the left stick, aims with the right stick and shoots with the
+// right trigger.
+pub struct GamepadInput {
+  controller: GameController,
+}
+
+impl GamepadInput {
+  pub fn new(controller: GameController) -> GamepadInput {
+    GamepadInput { controller }
+  }
+
+  // The SDL instance id, which identifies this controller in events.
+  pub fn instance_id(&self) -> u32 {
+    self.controller.instance_id()
+  }
+
+  // Returns the stick's direction, or (0, 0) if it's in the dead zone.
+  fn stick(&self, x_axis: Axis, y_axis: Axis) -> Point {
+    let (x, y) = (self.controller.axis(x_axis), self.controller.axis(y_axis));
+    // In i64, as both axes at -32768 would overflow an i32.
+    let dist_sq = (x as i64).pow(2) + (y as i64).pow(2);
+    if dist_sq < (STICK_DEAD_ZONE as i64).pow(2) {
+      return Point::new(Coord(0.), Coord(0.));
+    }
+    Point::new(Coord(x as f32), Coord(y as f32)).normalized()
+  }
+}
+
+impl InputSource for GamepadInput {
+  // Controller state is polled in command(), so there are no events to track.
+
+  fn command(&mut self, game: &game::State, player: usize, _camera: &Camera) -> PlayerCommand {
+    let aim_dir = self.stick(Axis::RightX, Axis::RightY);
+    let is_aiming = aim_dir.x != Coord(0.) || aim_dir.y != Coord(0.);
+    let aim_at = match game.players.get(player) {
+      Some(p) if is_aiming => Some(p.unit.pos + aim_dir * AIM_DIST),
+      _ => None,
+    };
+    PlayerCommand {
+      move_dir: self.stick(Axis::LeftX, Axis::LeftY),
+      aim_at,
+      shooting: self.controller.axis(Axis::TriggerRight) > TRIGGER_THRESHOLD,
+    }
+  }
+}
@@ -1,35 +1,63 @@
+mod ai;
+mod aseprite;
+mod camera;
 #[allow(dead_code)]
 mod dimensions;
 #[allow(dead_code)]
 mod draw;
+mod editor;
+#[allow(dead_code)]
+mod flow_field;
 #[allow(dead_code)]
 mod game;
+mod headless;
+mod input;
 #[allow(dead_code)]
 mod map;
+mod mapgen;
+#[allow(dead_code)]
+mod pathfinding;
+mod replay;
+mod sprite_registry;
 #[allow(dead_code)]
 mod sprite_sheet;
+mod tiled;
 
 extern crate rand;
 extern crate sdl2;
+extern crate serde;
+extern crate serde_json;
 
 use sdl2::event::Event;
 use sdl2::image;
 use sdl2::keyboard::Keycode;
-use sdl2::mouse::MouseButton;
 use sdl2::render::Canvas;
 use sdl2::ttf::Font;
 use sdl2::video::Window;
+use sdl2::GameControllerSubsystem;
 use sdl2::Sdl;
 
 use std::process::exit;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
-use crate::dimensions::{ToWorld, WindowPoint, WorldCoord, WorldPoint};
-use crate::draw::{draw_frame, CachingTextRenderer};
-use crate::sprite_sheet::SpriteSheet;
+use crate::camera::Camera;
+use crate::dimensions::WindowPoint;
+use crate::draw::{draw_frame, CachingTextRenderer, OptionalSprites};
+use crate::editor::Editor;
+use crate::input::{
+  GamepadInput, InputSource, KeyboardInput, KeyboardMouseInput, PlayerInput, PlayerKeys,
+};
+use crate::replay::Replay;
+use crate::sprite_registry::SpriteRegistry;
+
+// Where to load sprites from. See SpriteRegistry::from_paths for what these can
+// be.
+const SPRITE_PATHS: &[&str] = &["media/sprite-sheet.sps"];
 
-const SPRITE_SHEET_PATH: &str = "media/sprite-sheet.sps";
+// Size of maps made with --generate, in tiles.
+const GENERATED_MAP_WIDTH: u32 = 40;
+const GENERATED_MAP_HEIGHT: u32 = 30;
 
 const WINDOW_WIDTH: u32 = 1280;
 const WINDOW_HEIGHT: u32 = 720;
@@ -37,6 +65,12 @@ const WINDOW_HEIGHT: u32 = 720;
 const TARGET_FRAME_PER_SEC: u32 = 120;
 const TARGET_FRAME_DUR: Duration = Duration::new(0, 1_000_000_000u32 / TARGET_FRAME_PER_SEC);
 
+const TICK_DUR: Duration = Duration::new(0, 1_000_000_000u32 / game::TICKS_PER_SEC);
+// If we fall further behind than this, drop the extra time rather than trying
+// to catch up. Otherwise a long stall (eg. dragging the window) would be
+// followed by a burst of ticks.
+const MAX_TICKS_PER_FRAME: u32 = 5;
+
 const PERF_DEBUG: bool = false; // Enable this to get some perf debug info.
 macro_rules! perf {
   ($( $x:expr ),* ) => {
@@ -49,46 +83,68 @@ const LONG_FRAME_DEBUG: bool = false;
 #[allow(dead_code)]
 struct State<'canvas, 'b> {
   // "Immutable" stuff.
-  sprite_sheet: SpriteSheet<'canvas>,
+  sprite_registry: SpriteRegistry<'canvas>,
+  sprites: OptionalSprites,
   font: Font<'b, 'static>,
   text_renderer: CachingTextRenderer<'canvas>,
+  controller_subsystem: GameControllerSubsystem,
 
   // State of the game.
   running: bool,
   game: game::State,
+  // How far we are between the last tick and the next one, in [0, 1).
+  // Used to interpolate positions when rendering.
+  tick_alpha: f32,
+
+  // If we're recording, where to save it and the recording so far.
+  recording: Option<(String, Replay)>,
+  // The game is paused while the editor is open.
+  editor: Editor,
 
   // Interaction state.
   key_state: KeyState,
-  camera_pos: WorldPoint,
+  // The inputs driving each of game.players, by index.
+  inputs: Vec<PlayerInput>,
+  camera: Camera,
   mouse_pos: WindowPoint,
 }
 
 impl<'canvas, 'b> State<'canvas, 'b> {
   pub fn new<'s, 'f>(
-    sprite_sheet: SpriteSheet<'s>,
+    sprite_registry: SpriteRegistry<'s>,
     font: Font<'f, 'static>,
     text_renderer: CachingTextRenderer<'s>,
+    controller_subsystem: GameControllerSubsystem,
+    game: game::State,
+    recording: Option<(String, Replay)>,
   ) -> State<'s, 'f> {
     State {
-      sprite_sheet,
+      sprites: OptionalSprites::from_registry(&sprite_registry),
+      sprite_registry,
       font,
       text_renderer,
+      controller_subsystem,
 
       running: true,
-      game: game::State::level1(),
+      game,
+      tick_alpha: 0.,
+
+      recording,
+      editor: Editor::new(game::DEFAULT_MAP_PATH),
 
       key_state: KeyState::new(),
-      camera_pos: WorldPoint::new(WorldCoord(0.), WorldCoord(0.)),
+      inputs: vec![
+        PlayerInput::new(Box::new(KeyboardMouseInput::new(PlayerKeys::wasd()))),
+        PlayerInput::new(Box::new(KeyboardInput::new(
+          PlayerKeys::arrows(),
+          Keycode::RCtrl,
+        ))),
+      ],
+      camera: Camera::new(WINDOW_WIDTH, WINDOW_HEIGHT),
       // This is wrong, but will be set on the next MouseMotion event.
       mouse_pos: WindowPoint::new(0, 0),
     }
   }
-
-  // Returns a world point corresponding to the top-left corner of the
-  // renderable window.
-  pub fn camera_pos(&self) -> WorldPoint {
-    self.camera_pos
-  }
 }
 
 #[allow(dead_code)]
@@ -157,6 +213,38 @@ impl KeyState {
 }
 
 fn main() {
+  let args: Vec<String> = std::env::args().collect();
+  let mut record_path = None;
+  match args.get(1).map(String::as_str) {
+    Some("--headless") => {
+      run_headless(&args[2..]);
+      return;
+    }
+    Some("--replay") => {
+      run_replay(&args[2..]);
+      return;
+    }
+    Some("--generate") => {
+      run_generate(&args[2..]);
+      return;
+    }
+    Some("--record") => match &args[2..] {
+      [path] => record_path = Some(path.clone()),
+      _ => {
+        println!("usage: shooter --record REPLAY_PATH");
+        exit(2);
+      }
+    },
+    _ => {}
+  }
+
+  let seed = rand::random();
+  let mut game = game::State::level1(game::DEFAULT_MAP_PATH, seed).unwrap_or_else(|e| {
+    println!("error loading map \"{}\": {}", game::DEFAULT_MAP_PATH, e);
+    exit(1);
+  });
+  let recording = record_path.map(|path| (path, Replay::new(game::DEFAULT_MAP_PATH, seed)));
+
   let sdl_context = sdl2::init().unwrap();
   let _sdl_image_context = image::init(image::InitFlag::PNG).unwrap();
 
@@ -166,6 +254,7 @@ fn main() {
     .expect("couldn't load font");
 
   let video = sdl_context.video().unwrap();
+  let controller_subsystem = sdl_context.game_controller().unwrap();
 
   let window = video
     .window("rts!", WINDOW_WIDTH, WINDOW_HEIGHT)
@@ -176,25 +265,110 @@ fn main() {
   let canvas = window.into_canvas().software().build().unwrap();
   let canvas_txc = canvas.texture_creator();
 
-  let sprite_sheet = SpriteSheet::from_file(SPRITE_SHEET_PATH, &canvas_txc).unwrap_or_else(|e| {
-    println!(
-      "error loading sprite sheet \"{}\": {}",
-      SPRITE_SHEET_PATH, e
-    );
+  let sprite_registry = SpriteRegistry::from_paths(SPRITE_PATHS, &canvas_txc).unwrap_or_else(|e| {
+    println!("error loading sprites: {}", e);
     exit(1);
   });
+  // Catch missing sprites now, rather than when they're first drawn.
+  for key in game.sprite_keys_mut() {
+    sprite_registry.resolve(key).unwrap_or_else(|e| {
+      println!("error loading sprites: {}", e);
+      exit(1);
+    });
+  }
 
   let text_renderer = CachingTextRenderer::new(&canvas_txc);
 
-  let state = { State::new(sprite_sheet, font, text_renderer) };
+  let state = {
+    State::new(
+      sprite_registry,
+      font,
+      text_renderer,
+      controller_subsystem,
+      game,
+      recording,
+    )
+  };
   main_loop(state, canvas, sdl_context);
 }
 
+// Usage: --headless N_TICKS SCRIPT_PATH
+fn run_headless(args: &[String]) {
+  let (n_ticks, script_path) = match args {
+    [n_ticks, script_path] => (n_ticks, script_path),
+    _ => {
+      println!("usage: shooter --headless N_TICKS SCRIPT_PATH");
+      exit(2);
+    }
+  };
+  let n_ticks: u32 = n_ticks.parse().unwrap_or_else(|e| {
+    println!("error parsing N_TICKS \"{}\": {}", n_ticks, e);
+    exit(2);
+  });
+  headless::run(n_ticks, script_path).unwrap_or_else(|e| {
+    println!("error running script \"{}\": {}", script_path, e);
+    exit(1);
+  });
+}
+
+// Usage: --replay REPLAY_PATH
+fn run_replay(args: &[String]) {
+  let path = match args {
+    [path] => path,
+    _ => {
+      println!("usage: shooter --replay REPLAY_PATH");
+      exit(2);
+    }
+  };
+  replay::run(path).unwrap_or_else(|e| {
+    println!("error playing replay \"{}\": {}", path, e);
+    exit(1);
+  });
+}
+
+// Usage: --generate STYLE SEED MAP_PATH
+fn run_generate(args: &[String]) {
+  let (style, seed, path) = match args {
+    [style, seed, path] => (style, seed, path),
+    _ => {
+      println!("usage: shooter --generate caves|rooms|arena SEED MAP_PATH");
+      exit(2);
+    }
+  };
+  let style = mapgen::Style::from_name(style).unwrap_or_else(|e| {
+    println!("error: {}", e);
+    exit(2);
+  });
+  let seed: u64 = seed.parse().unwrap_or_else(|e| {
+    println!("error parsing SEED \"{}\": {}", seed, e);
+    exit(2);
+  });
+  let params = mapgen::Params {
+    style,
+    width: GENERATED_MAP_WIDTH,
+    height: GENERATED_MAP_HEIGHT,
+    n_player_spawns: game::N_PLAYERS,
+  };
+  let map = mapgen::generate(&params, seed).unwrap_or_else(|e| {
+    println!("error generating map: {}", e);
+    exit(1);
+  });
+  map.save(path).unwrap_or_else(|e| {
+    println!("error saving map \"{}\": {}", path, e);
+    exit(1);
+  });
+  println!("saved map to \"{}\"", path);
+}
+
 fn main_loop(mut state: State, mut canvas: Canvas<Window>, sdl_context: Sdl) {
   let mut event_pump = sdl_context.event_pump().unwrap();
   let mut mean_frame_dur = Duration::from_nanos(0);
+  let mut tick_accumulator = Duration::from_nanos(0);
+  let mut last_frame_start = Instant::now();
   while state.running {
     let frame_start = Instant::now();
+    tick_accumulator += frame_start - last_frame_start;
+    last_frame_start = frame_start;
 
     // Handle input.
     for event in event_pump.poll_iter() {
@@ -202,9 +376,32 @@ fn main_loop(mut state: State, mut canvas: Canvas<Window>, sdl_context: Sdl) {
     }
     let events_done = Instant::now();
 
-    // Update world.
-    // TODO: Make game ticks operate on a different clock than render ticks.
-    state.game.tick();
+    // Update world. Ticks run on their own fixed clock, so we run as many as
+    // have accumulated since the last frame.
+    if state.editor.open {
+      tick_accumulator = Duration::from_nanos(0);
+    }
+    let mut ticks_this_frame = 0;
+    while tick_accumulator >= TICK_DUR {
+      if ticks_this_frame == MAX_TICKS_PER_FRAME {
+        tick_accumulator = Duration::from_nanos(0);
+        break;
+      }
+      let commands: Vec<_> = state
+        .inputs
+        .iter_mut()
+        .enumerate()
+        .map(|(i, input)| input.command(&state.game, i, &state.camera))
+        .collect();
+      if let Some((_, replay)) = &mut state.recording {
+        replay.record(&commands);
+      }
+      state.game.tick(&commands);
+      tick_accumulator -= TICK_DUR;
+      ticks_this_frame += 1;
+    }
+    state.tick_alpha = tick_accumulator.as_secs_f32() / TICK_DUR.as_secs_f32();
+    state.camera.follow(&state.game, state.tick_alpha);
     let tick_done = Instant::now();
 
     // Render.
@@ -246,10 +443,24 @@ fn main_loop(mut state: State, mut canvas: Canvas<Window>, sdl_context: Sdl) {
       present_done - render_done
     );
   }
+
+  if let Some((path, mut replay)) = state.recording.take() {
+    replay.finish(&state.game);
+    match replay.save(&path) {
+      Ok(()) => println!("saved replay to \"{}\"", path),
+      Err(e) => println!("error saving replay \"{}\": {}", path, e),
+    }
+  }
 }
 
 fn handle_event(state: &mut State, _canvas: &mut Canvas<Window>, event: Event) {
+  let editor_used = state.editor.open
+    && state
+      .editor
+      .handle_event(&mut state.game, &state.camera, &event);
   match event {
+    _ if editor_used => {}
+
     // Quit.
     Event::Quit { .. }
     | Event::KeyDown {
@@ -259,83 +470,80 @@ fn handle_event(state: &mut State, _canvas: &mut Canvas<Window>, event: Event) {
       state.running = false;
     }
 
-    // Left mouse down / up: box select.
-    Event::MouseButtonDown {
-      x,
-      y,
-      mouse_btn: MouseButton::Left,
+    Event::KeyDown {
+      repeat: false,
+      keycode,
       ..
     } => {
-      for player in state.game.players.iter_mut() {
-        // TODO: When using gamepads, separate out who's who.
-        player.unit.shooting = true;
+      state.key_state.update_shift_alt_ctrl(keycode, true);
+      match keycode {
+        Some(Keycode::C) => state.camera.cycle_target(state.game.players.len()),
+        Some(Keycode::Z) => state.camera.fit_players = !state.camera.fit_players,
+        Some(Keycode::Tab) => toggle_editor(state),
+        _ => {}
       }
     }
-    Event::MouseButtonUp {
-      x,
-      y,
-      mouse_btn: MouseButton::Left,
-      ..
-    } => {
-      for player in state.game.players.iter_mut() {
-        // TODO: When using gamepads, separate out who's who.
-        player.unit.shooting = false;
-      }
+    Event::KeyUp { keycode, .. } => {
+      state.key_state.update_shift_alt_ctrl(keycode, false);
     }
 
-    Event::MouseMotion {
-      x, y, xrel, yrel, ..
-    } => {
-      let mouse_pos_world = WindowPoint::new(x, y).to_world(state.camera_pos);
-      for player in state.game.players.iter_mut() {
-        // TODO: When using gamepads, separate out who's who.
-        player.unit.aim_at(mouse_pos_world);
-      }
+    Event::MouseMotion { x, y, .. } => {
+      state.mouse_pos = WindowPoint::new(x, y);
     }
 
-    Event::KeyDown {
-      repeat: false,
-      keycode,
-      ..
-    } => {
-      state.key_state.update_shift_alt_ctrl(keycode, true);
-      if let Some(keycode) = keycode {
-        for player in state.game.players.iter_mut() {
-          if keycode == player.keys.up {
-            player.unit.move_dir.y.0 -= 1.0;
-          }
-          if keycode == player.keys.down {
-            player.unit.move_dir.y.0 += 1.0;
-          }
-          if keycode == player.keys.left {
-            player.unit.move_dir.x.0 -= 1.0;
-          }
-          if keycode == player.keys.right {
-            player.unit.move_dir.x.0 += 1.0;
-          }
+    Event::MouseWheel { y, .. } => {
+      state.camera.zoom_by(y);
+    }
+
+    // Gamepads are bound to the first player who doesn't have one yet. This
+    // also fires at startup for gamepads that are already plugged in.
+    Event::ControllerDeviceAdded { which, .. } => {
+      let controller = match state.controller_subsystem.open(which) {
+        Ok(c) => c,
+        Err(e) => {
+          println!("error opening controller {}: {}", which, e);
+          return;
+        }
+      };
+      match state.inputs.iter_mut().position(|i| i.gamepad.is_none()) {
+        Some(player) => {
+          println!(
+            "binding controller \"{}\" to player {}",
+            controller.name(),
+            player
+          );
+          state.inputs[player].gamepad = Some(GamepadInput::new(controller));
         }
+        None => println!("no free player for controller \"{}\"", controller.name()),
       }
     }
-    Event::KeyUp { keycode, .. } => {
-      state.key_state.update_shift_alt_ctrl(keycode, false);
-      if let Some(keycode) = keycode {
-        for player in state.game.players.iter_mut() {
-          if keycode == player.keys.up {
-            player.unit.move_dir.y.0 += 1.0;
-          }
-          if keycode == player.keys.down {
-            player.unit.move_dir.y.0 -= 1.0;
-          }
-          if keycode == player.keys.left {
-            player.unit.move_dir.x.0 += 1.0;
-          }
-          if keycode == player.keys.right {
-            player.unit.move_dir.x.0 -= 1.0;
-          }
+    Event::ControllerDeviceRemoved { which, .. } => {
+      for (player, input) in state.inputs.iter_mut().enumerate() {
+        if input.gamepad_id() == Some(which) {
+          println!("unbinding controller from player {}", player);
+          input.gamepad = None;
         }
       }
     }
 
     _ => {}
   }
+
+  for input in state.inputs.iter_mut() {
+    input.handle_event(&event);
+  }
+}
+
+fn toggle_editor(state: &mut State) {
+  // A replay only records the map's path, so it couldn't be played back on an
+  // edited map.
+  if !state.editor.open && state.recording.is_some() {
+    println!("can't edit the map while recording");
+    return;
+  }
+  state.editor.open = !state.editor.open;
+  if !state.editor.open {
+    // The map may have changed under the players.
+    state.game.flow_fields.invalidate();
+  }
 }
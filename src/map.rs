@@ -1,18 +1,52 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::ops::Add;
+use std::str::{FromStr, Lines};
 
 use crate::dimensions::{WorldCoord as Coord, WorldPoint as Point, WorldRect as Rect};
+use crate::tiled;
 
 pub const TILE_WIDTH: u32 = 64;
 pub const TILE_WIDTH_F32: f32 = 64.;
+// The most tiles a map can have along either side. Tile indexes on the biggest
+// map still fit in a u32, and a bad size in a file can't make us try to
+// allocate more memory than there is.
+pub const MAX_MAP_SIDE: u32 = 4096;
 
+// The first line of a versioned map file. Files without it are version 1.
+const HEADER: &str = "shooter-map 2";
+
+// The legend that maps are written with. Any legend can be read.
+const LEGEND: [(char, GridTile); 4] = [
+  ('O', GridTile::Empty),
+  ('X', GridTile::Obstacle),
+  ('~', GridTile::Water),
+  (',', GridTile::Mud),
+];
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Map {
   // Width and height are measured in grid units.
   pub width: u32,
   pub height: u32,
 
   pub grid_tiles: Vec<GridTile>,
+
+  // Where each player starts, by player index.
+  pub player_spawns: Vec<TilePoint>,
+  pub spawners: Vec<Spawner>,
+  pub pickups: Vec<PickupSpawn>,
+  pub regions: Vec<Region>,
+
+  // Extra settings from the level editor, for level logic to use. Maps read
+  // from our own format have none.
+  pub properties: Properties,
 }
 
+// Custom properties, by name. Values are kept as text, however the editor
+// typed them.
+pub type Properties = BTreeMap<String, String>;
+
 // A tile is square with side length L:
 //
 // p1--p2
@@ -25,59 +59,87 @@ pub struct Map {
 // actually in the tile. The tile is left- and up- inclusive and right-
 // and down- exclusive.
 impl Map {
-  // Reads a Map from a file.
-  //
-  // The format is like so:
-  // WIDTH
-  // HEIGHT
-  // TILES
-  //
-  // TILES is a grid of WIDTH by HEIGHT tile entries. Each entry is either X for
-  // a wall or O for open.
+  // Reads a Map from a file. See Map::from_str for the format, or tiled.rs
+  // for the .tmx and .tmj files that the Tiled editor saves.
   pub fn from_file(path: &str) -> Result<Map, String> {
     let file = std::fs::read_to_string(path).map_err(|e| format!("err reading file: {:?}", e))?;
-    let mut lines = file.lines();
-
-    let width: u32 = lines
-      .next()
-      .ok_or("map missing WIDTH")?
-      .parse()
-      .or(Err("failed to parse WIDTH"))?;
-    let height: u32 = lines
-      .next()
-      .ok_or("map missing HEIGHT")?
+    if path.ends_with(".tmx") {
+      return tiled::from_tmx(&file).map_err(|e| format!("{}: {}", path, e));
+    }
+    if path.ends_with(".tmj") || path.ends_with(".json") {
+      return tiled::from_json(&file).map_err(|e| format!("{}: {}", path, e));
+    }
+    file
       .parse()
-      .or(Err("failed to parse WIDTH"))?;
-    let mut grid_tiles = Vec::new();
-    grid_tiles.reserve(width as usize * height as usize);
-    for i in 0..height {
-      let row = lines.next().ok_or(format!(
-        "map ends at row {} of expected HEIGHT={}",
-        i, height
-      ))?;
-      if row.len() == width as usize {
-        for c in row.chars() {
-          match c {
-            'X' => grid_tiles.push(GridTile::Obstacle),
-            'O' => grid_tiles.push(GridTile::Empty),
-            _ => {}
-          }
-        }
-      } else {
-        return Err(format!(
-          "row {} has length {}, when it should be WIDTH={}",
-          i,
-          row.len(),
-          width
-        ));
-      }
+      .map_err(|e: MapParseError| format!("{}: {}", path, e))
+  }
+
+  // Writes the map to a file, in the format that Map::from_str reads. Fails
+  // without writing anything if an entity couldn't be read back, such as a
+  // region whose name has a space in it.
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    for entity in self.entities() {
+      self.check_entity(&entity)?;
     }
+    std::fs::write(path, self.to_string()).map_err(|e| format!("err writing file: {:?}", e))
+  }
 
-    Ok(Map {
+  // A map with the given tiles and no entities.
+  pub fn new(width: u32, height: u32, grid_tiles: Vec<GridTile>) -> Map {
+    Map {
       width,
       height,
       grid_tiles,
-    })
+      player_spawns: vec![],
+      spawners: vec![],
+      pickups: vec![],
+      regions: vec![],
+      properties: Properties::new(),
+    }
+  }
+
+  // Every entity on the map, in the order they're written to a file.
+  pub fn entities(&self) -> impl Iterator<Item = MapEntity> + '_ {
+    let spawns = self
+      .player_spawns
+      .iter()
+      .map(|p| MapEntity::PlayerSpawn(*p));
+    let spawners = self.spawners.iter().cloned().map(MapEntity::Spawner);
+    let pickups = self.pickups.iter().cloned().map(MapEntity::Pickup);
+    let regions = self.regions.iter().cloned().map(MapEntity::Region);
+    spawns.chain(spawners).chain(pickups).chain(regions)
+  }
+
+  // Checks that entity is placed inside the map, that spawns are on tiles
+  // that units can stand on, and that names can be written to a map file.
+  pub fn check_entity(&self, entity: &MapEntity) -> Result<(), String> {
+    let standable = |what: &str, p: TilePoint| match self.get_tile(p) {
+      None => Err(format!("{} at ({}, {}) is off the map", what, p.x, p.y)),
+      Some(tile) if tile.blocks_movement() => {
+        Err(format!("{} at ({}, {}) is in a {:?}", what, p.x, p.y, tile))
+      }
+      Some(_) => Ok(()),
+    };
+    match entity {
+      MapEntity::PlayerSpawn(p) => standable("player spawn", *p),
+      MapEntity::Spawner(spawner) => {
+        check_name("spawner kind", &spawner.kind)?;
+        standable("spawner", spawner.pos)
+      }
+      MapEntity::Pickup(pickup) => standable("pickup", pickup.pos),
+      MapEntity::Region(region) => {
+        check_name("region name", &region.name)?;
+        if !region.fits_in(self.width, self.height) {
+          return Err(format!("region \"{}\" goes off the map", region.name));
+        }
+        Ok(())
+      }
+    }
+  }
+
+  // Returns the region with the given name, if there is one.
+  pub fn region(&self, name: &str) -> Option<&Region> {
+    self.regions.iter().find(|r| r.name == name)
   }
 
   pub fn get_tile(&self, p: TilePoint) -> Option<GridTile> {
@@ -92,6 +154,35 @@ impl Map {
     Some(self.grid_tiles[index])
   }
 
+  // Sets the tile at p. Returns false, changing nothing, if p is off the map.
+  pub fn set_tile(&mut self, p: TilePoint, tile: GridTile) -> bool {
+    if self.get_tile(p).is_none() {
+      return false;
+    }
+    self.grid_tiles[(p.x + p.y * self.width) as usize] = tile;
+    true
+  }
+
+  // Changes the size of the map, keeping its top-left corner where it is. New
+  // tiles are floor, and entities that end up off the map are dropped.
+  pub fn resize(&mut self, width: u32, height: u32) {
+    let mut grid_tiles = vec![GridTile::Empty; (width * height) as usize];
+    for y in 0..u32::min(height, self.height) {
+      for x in 0..u32::min(width, self.width) {
+        grid_tiles[(x + y * width) as usize] = self.get_tile_unchecked(x, y);
+      }
+    }
+    self.width = width;
+    self.height = height;
+    self.grid_tiles = grid_tiles;
+
+    let on_map = |p: &TilePoint| p.x < width && p.y < height;
+    self.player_spawns.retain(on_map);
+    self.spawners.retain(|s| on_map(&s.pos));
+    self.pickups.retain(|p| on_map(&p.pos));
+    self.regions.retain(|r| r.fits_in(width, height));
+  }
+
   fn get_tile_unchecked(&self, x: u32, y: u32) -> GridTile {
     self.grid_tiles[(x + y * self.width) as usize]
   }
@@ -114,6 +205,10 @@ impl Map {
     let bot_right = rect.top_left + Point::new(rect.width, rect.height);
     let (top_left_x, top_left_y) = self.tile_coords_at_unchecked(top_left.clamp(&bounds));
     let (bot_right_x, bot_right_y) = self.tile_coords_at_unchecked(bot_right.clamp(&bounds));
+    // Points on the right or bottom edge of the map are just past the last
+    // tile, so pull them back in.
+    let bot_right_x = bot_right_x.min(self.width - 1);
+    let bot_right_y = bot_right_y.min(self.height - 1);
     let width = bot_right_x - top_left_x + 1; // +1 to include the cur.
     let height = bot_right_y - top_left_y + 1; // +1 to include the cur.
 
@@ -129,7 +224,7 @@ impl Map {
     }
   }
 
-  fn bounds(&self) -> Rect {
+  pub fn bounds(&self) -> Rect {
     Rect {
       top_left: Point::new(Coord(0.), Coord(0.)),
       width: Coord((self.width * TILE_WIDTH) as f32),
@@ -171,6 +266,180 @@ impl Map {
     }
     false
   }
+
+  // Whether a unit with this bounding box would overlap something it can't
+  // walk through.
+  pub fn rect_blocks_movement(&self, rect: Rect) -> bool {
+    self
+      .tiles_overlapping_rect(rect)
+      .any(|t| t.tile.blocks_movement())
+  }
+
+  // Casts a ray from `from` to `to`, and returns where it first runs into a
+  // tile that stops bullets, or None if it gets to `to` without hitting one.
+  // A ray starting inside a wall hits it straight away.
+  //
+  // The ray hits every tile that any point along it is in, going by the
+  // rules above impl Map for which tile a point is in. So a ray ending
+  // exactly on a wall's left or top edge hits it, but one ending on its right
+  // or bottom edge doesn't. A ray passing exactly through the corner between
+  // four tiles touches the one below and right of the corner, as well as the
+  // ones it's in before and after, and no others.
+  //
+  // Tiles off the map never block rays. Useful for what units can see, or
+  // anything else that travels in a straight line.
+  pub fn raycast(&self, from: Point, to: Point) -> Option<RayHit> {
+    let delta = to - from;
+    let axes = [
+      RayAxis::new(from.x.0, delta.x.0),
+      RayAxis::new(from.y.0, delta.y.0),
+    ];
+    let mut tile = [axes[0].start_tile(), axes[1].start_tile()];
+    // Where the ray got into the current tile.
+    let mut point = from;
+    loop {
+      if self
+        .get_tile_i64(tile[0], tile[1])
+        .is_some_and(GridTile::blocks_bullets)
+      {
+        return Some(RayHit {
+          tile: TilePoint::new(tile[0] as u32, tile[1] as u32),
+          point,
+          distance: (point - from).magnitude(),
+        });
+      }
+
+      // Step into the next tile along whichever axis the ray crosses a tile
+      // edge on first. Stepping right or down happens exactly at the edge,
+      // while stepping left or up only happens just after it, so ties go to
+      // steps right or down. Ties between steps the same way are taken
+      // together, so the ray goes straight through the corner.
+      let crossings = [0, 1].map(|i| axes[i].next_crossing(tile[i]));
+      let next = crossings.iter().flatten().min_by(|a, b| a.total_cmp(b));
+      let next = match next {
+        Some(&next) => next,
+        None => return None,
+      };
+      // Points on the edges crossed are snapped to them exactly, as working
+      // them out from t can be off by a rounding error.
+      let mut coords = [0, 1].map(|i| axes[i].start + axes[i].delta * next.t);
+      for i in 0..2 {
+        if let Some(crossing) = crossings[i].filter(|c| c.total_cmp(&next).is_eq()) {
+          tile[i] += axes[i].step;
+          coords[i] = crossing.edge;
+        }
+      }
+      point = Point::new(Coord(coords[0]), Coord(coords[1]));
+    }
+  }
+
+  // Whether nothing that stops bullets lies on the straight line from a to b.
+  pub fn line_of_sight(&self, a: Point, b: Point) -> bool {
+    self.raycast(a, b).is_none()
+  }
+
+  fn get_tile_i64(&self, x: i64, y: i64) -> Option<GridTile> {
+    if x < 0 || y < 0 || x > u32::MAX as i64 || y > u32::MAX as i64 {
+      return None;
+    }
+    self.get_tile(TilePoint::new(x as u32, y as u32))
+  }
+
+  // The tile nearest p, as the crow flies, that units can stand on, or None if
+  // there isn't one. Ties go to the first in reading order.
+  pub fn nearest_open_tile(&self, p: Point) -> Option<TilePoint> {
+    let dist = |t: &TilePoint| (t.center_to_world_point() - p).magnitude().0;
+    self
+      .tiles()
+      .filter(|t| !t.tile.blocks_movement())
+      .map(|t| t.pos)
+      .min_by(|a, b| dist(a).total_cmp(&dist(b)))
+  }
+
+  // How fast units move at this point, as a fraction of their usual speed.
+  pub fn speed_factor_at(&self, point: Point) -> f32 {
+    self
+      .get_tile_at(point)
+      .map_or(1., |tile| tile.speed_factor())
+  }
+}
+
+// Where a ray cast with Map::raycast ran into a wall.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+  pub tile: TilePoint,
+  // Where the ray first touched the tile, and how far that is from the start
+  // of the ray.
+  pub point: Point,
+  pub distance: Coord,
+}
+
+// A ray's progress along one axis, for Map::raycast.
+struct RayAxis {
+  start: f32,
+  delta: f32,
+  // Which way the ray goes from tile to tile along this axis: -1, 0 or 1.
+  step: i64,
+}
+
+// When a ray next crosses a tile edge along one of its axes.
+#[derive(Clone, Copy)]
+struct RayCrossing {
+  // How far along the ray it is, from 0 at its start to 1 at its end.
+  t: f32,
+  // Where the edge is along the axis.
+  edge: f32,
+  // Whether the crossing is to the left or up, which happens just after t
+  // rather than exactly at it.
+  backwards: bool,
+}
+
+impl RayCrossing {
+  fn total_cmp(&self, other: &RayCrossing) -> std::cmp::Ordering {
+    self
+      .t
+      .total_cmp(&other.t)
+      .then(self.backwards.cmp(&other.backwards))
+  }
+}
+
+impl RayAxis {
+  fn new(start: f32, delta: f32) -> RayAxis {
+    let step = if delta > 0. {
+      1
+    } else if delta < 0. {
+      -1
+    } else {
+      0
+    };
+    RayAxis { start, delta, step }
+  }
+
+  fn start_tile(&self) -> i64 {
+    (self.start / TILE_WIDTH_F32).floor() as i64
+  }
+
+  // Where the ray leaves tile along this axis, or None if it ends first. The
+  // edge position is worked out afresh from the tile each time, rather than
+  // added up step by step, so that rays along tile edges stay exact.
+  fn next_crossing(&self, tile: i64) -> Option<RayCrossing> {
+    let edge = match self.step {
+      1 => tile + 1,
+      -1 => tile,
+      _ => return None,
+    };
+    let edge = edge as f32 * TILE_WIDTH_F32;
+    let t = (edge - self.start) / self.delta;
+    let backwards = self.step < 0;
+    // A ray that ends exactly on an edge ends in the tile right of or below
+    // it.
+    let crosses = if backwards { t < 1. } else { t <= 1. };
+    if crosses {
+      Some(RayCrossing { t, edge, backwards })
+    } else {
+      None
+    }
+  }
 }
 
 pub struct MapTileIterator<'a> {
@@ -273,13 +542,576 @@ impl Iterator for MapTileRectIterator<'_> {
   }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+impl FromStr for Map {
+  type Err = MapParseError;
+
+  // Parses a map file.
+  //
+  // The format is like so:
+  // shooter-map 2
+  // size WIDTH HEIGHT
+  // legend N_GLYPHS
+  // GLYPHS
+  // tiles
+  // TILES
+  // entities N_ENTITIES
+  // ENTITIES
+  //
+  // GLYPHS is N_GLYPHS lines of "GLYPH TILE", where GLYPH is a single
+  // character and TILE is parsed by GridTile::from_str. TILES is a grid of
+  // WIDTH by HEIGHT glyphs, each of which must be in the legend. ENTITIES is
+  // N_ENTITIES lines, each parsed by MapEntity::from_str. Entities are placed
+  // by tile coordinates.
+  //
+  // Version 1 files, without the header, are just:
+  // WIDTH
+  // HEIGHT
+  // TILES
+  //
+  // where each entry in TILES is either X for a wall or O for open. They have
+  // no entities.
+  //
+  // Nothing but blank lines may come after the last section.
+  fn from_str(file: &str) -> Result<Self, Self::Err> {
+    let mut lines = LineReader::new(file);
+
+    if file.lines().next() != Some(HEADER) {
+      let width = lines.next("WIDTH")?;
+      let width = lines.size(width, 1, "WIDTH")?;
+      let height = lines.next("HEIGHT")?;
+      let height = lines.size(height, 1, "HEIGHT")?;
+      let legend = HashMap::from([('X', GridTile::Obstacle), ('O', GridTile::Empty)]);
+      let grid_tiles = lines.tiles(width, height, &legend)?;
+      lines.end()?;
+      return Ok(Map::new(width, height, grid_tiles));
+    }
+    lines.next("header")?;
+
+    let size = lines.field("size", "size WIDTH HEIGHT")?;
+    let (width, height) = size.split_once(' ').ok_or(lines.error(
+      1,
+      MapParseErrorKind::Expected {
+        expected: "size WIDTH HEIGHT",
+        found: lines.current.to_string(),
+      },
+    ))?;
+    let width = lines.size(width, "size ".len() + 1, "WIDTH")?;
+    let height = lines.size(height, lines.current.len() - height.len() + 1, "HEIGHT")?;
+
+    let n_glyphs = lines.field("legend", "legend N_GLYPHS")?;
+    let n_glyphs = lines.number(n_glyphs, "legend ".len() + 1, "N_GLYPHS")?;
+    let mut legend = HashMap::new();
+    for _ in 0..n_glyphs {
+      let line = lines.next("legend entry")?;
+      let mut chars = line.chars();
+      let (glyph, tile) = match (chars.next(), chars.next()) {
+        (Some(glyph), Some(' ')) => (glyph, chars.as_str()),
+        _ => {
+          return Err(lines.error(
+            1,
+            MapParseErrorKind::Expected {
+              expected: "GLYPH TILE",
+              found: line.to_string(),
+            },
+          ))
+        }
+      };
+      let tile = tile
+        .parse()
+        .map_err(|_| lines.error(3, MapParseErrorKind::UnknownTile(tile.to_string())))?;
+      if legend.insert(glyph, tile).is_some() {
+        return Err(lines.error(1, MapParseErrorKind::DuplicateGlyph(glyph)));
+      }
+    }
+
+    let line = lines.next("tiles")?;
+    if line != "tiles" {
+      return Err(lines.error(
+        1,
+        MapParseErrorKind::Expected {
+          expected: "tiles",
+          found: line.to_string(),
+        },
+      ));
+    }
+    let grid_tiles = lines.tiles(width, height, &legend)?;
+    let mut map = Map::new(width, height, grid_tiles);
+
+    let n_entities = lines.field("entities", "entities N_ENTITIES")?;
+    let n_entities = lines.number(n_entities, "entities ".len() + 1, "N_ENTITIES")?;
+    for _ in 0..n_entities {
+      let line = lines.next("entity")?;
+      let entity: MapEntity = line
+        .parse()
+        .map_err(|e| lines.error(1, MapParseErrorKind::BadEntity(e)))?;
+      map
+        .check_entity(&entity)
+        .map_err(|e| lines.error(1, MapParseErrorKind::BadEntity(e)))?;
+      match entity {
+        MapEntity::PlayerSpawn(p) => map.player_spawns.push(p),
+        MapEntity::Spawner(s) => map.spawners.push(s),
+        MapEntity::Pickup(p) => map.pickups.push(p),
+        MapEntity::Region(r) => map.regions.push(r),
+      }
+    }
+    lines.end()?;
+
+    Ok(map)
+  }
+}
+
+impl fmt::Display for Map {
+  // Writes the map in the version 2 format read by Map::from_str, with the
+  // glyphs in LEGEND and the entities grouped by kind. Reading it back gives
+  // the same map, and files written this way are reproduced exactly, as long
+  // as every entity passes Map::check_entity. Map::save makes sure of that.
+  //
+  // Custom properties aren't part of the format, so they're left out.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{}", HEADER)?;
+    writeln!(f, "size {} {}", self.width, self.height)?;
+    writeln!(f, "legend {}", LEGEND.len())?;
+    for (glyph, tile) in LEGEND.iter() {
+      writeln!(f, "{} {}", glyph, tile.name())?;
+    }
+    writeln!(f, "tiles")?;
+    for row in self.grid_tiles.chunks(self.width as usize) {
+      let row: String = row.iter().map(|t| t.glyph()).collect();
+      writeln!(f, "{}", row)?;
+    }
+
+    let n_entities =
+      self.player_spawns.len() + self.spawners.len() + self.pickups.len() + self.regions.len();
+    writeln!(f, "entities {}", n_entities)?;
+    for p in self.player_spawns.iter() {
+      writeln!(f, "player_spawn {} {}", p.x, p.y)?;
+    }
+    for s in self.spawners.iter() {
+      writeln!(f, "spawner {} {} {}", s.kind, s.pos.x, s.pos.y)?;
+    }
+    for p in self.pickups.iter() {
+      writeln!(f, "pickup {} {} {}", p.kind.name(), p.pos.x, p.pos.y)?;
+    }
+    for r in self.regions.iter() {
+      writeln!(
+        f,
+        "region {} {} {} {} {}",
+        r.name, r.top_left.x, r.top_left.y, r.width, r.height
+      )?;
+    }
+    Ok(())
+  }
+}
+
+// What went wrong parsing a map, and where.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapParseError {
+  // Both start from 1. Errors about a whole line point at its first column,
+  // and errors about a missing line point just past the end of the file.
+  pub line: usize,
+  pub column: usize,
+  pub kind: MapParseErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapParseErrorKind {
+  // The file ended where we expected this.
+  Missing(&'static str),
+  // The line didn't have the shape we expected.
+  Expected {
+    expected: &'static str,
+    found: String,
+  },
+  BadNumber {
+    field: &'static str,
+    found: String,
+  },
+  UnknownTile(String),
+  DuplicateGlyph(char),
+  // A glyph in TILES that isn't in the legend.
+  UnknownGlyph(char),
+  // A row of TILES with the wrong number of glyphs.
+  RowLength {
+    expected: u32,
+    found: usize,
+  },
+  // An entity that couldn't be parsed or doesn't fit on the map.
+  BadEntity(String),
+  // Something other than blank lines after the last section, such as an
+  // extra row of tiles.
+  Trailing(String),
+}
+
+impl fmt::Display for MapParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}, column {}: ", self.line, self.column)?;
+    match &self.kind {
+      MapParseErrorKind::Missing(what) => write!(f, "map ends before {}", what),
+      MapParseErrorKind::Expected { expected, found } => {
+        write!(f, "expected \"{}\", found \"{}\"", expected, found)
+      }
+      MapParseErrorKind::BadNumber { field, found } => {
+        write!(f, "failed to parse {} from \"{}\"", field, found)
+      }
+      MapParseErrorKind::UnknownTile(tile) => write!(f, "unknown tile \"{}\"", tile),
+      MapParseErrorKind::DuplicateGlyph(glyph) => {
+        write!(f, "glyph '{}' is in the legend twice", glyph)
+      }
+      MapParseErrorKind::UnknownGlyph(glyph) => {
+        write!(f, "glyph '{}' isn't in the legend", glyph)
+      }
+      MapParseErrorKind::RowLength { expected, found } => write!(
+        f,
+        "row has length {}, when it should be WIDTH={}",
+        found, expected
+      ),
+      MapParseErrorKind::BadEntity(e) => write!(f, "{}", e),
+      MapParseErrorKind::Trailing(line) => {
+        write!(f, "unexpected \"{}\" after the end of the map", line)
+      }
+    }
+  }
+}
+
+// Hands out the lines of a map file, keeping track of where we are so that
+// errors can say where they happened.
+struct LineReader<'a> {
+  lines: Lines<'a>,
+  // The line last returned by next(), and its number, starting from 1.
+  current: &'a str,
+  line_no: usize,
+}
+
+impl<'a> LineReader<'a> {
+  fn new(file: &'a str) -> LineReader<'a> {
+    LineReader {
+      lines: file.lines(),
+      current: "",
+      line_no: 0,
+    }
+  }
+
+  // Returns the next line, or an error saying that what was missing.
+  fn next(&mut self, what: &'static str) -> Result<&'a str, MapParseError> {
+    self.line_no += 1;
+    self.current = self.lines.next().ok_or(MapParseError {
+      line: self.line_no,
+      column: 1,
+      kind: MapParseErrorKind::Missing(what),
+    })?;
+    Ok(self.current)
+  }
+
+  // An error at the given column of the current line.
+  fn error(&self, column: usize, kind: MapParseErrorKind) -> MapParseError {
+    MapParseError {
+      line: self.line_no,
+      column,
+      kind,
+    }
+  }
+
+  // Reads a "NAME VALUE" line, returning VALUE.
+  fn field(&mut self, name: &'static str, form: &'static str) -> Result<&'a str, MapParseError> {
+    let line = self.next(name)?;
+    line
+      .strip_prefix(name)
+      .and_then(|l| l.strip_prefix(' '))
+      .ok_or(self.error(
+        1,
+        MapParseErrorKind::Expected {
+          expected: form,
+          found: line.to_string(),
+        },
+      ))
+  }
+
+  // Parses s, found at column of the current line, as a number.
+  fn number(&self, s: &str, column: usize, field: &'static str) -> Result<u32, MapParseError> {
+    s.parse().map_err(|_| {
+      self.error(
+        column,
+        MapParseErrorKind::BadNumber {
+          field,
+          found: s.to_string(),
+        },
+      )
+    })
+  }
+
+  // Like number, but for a map's width or height, which must be from 1 to
+  // MAX_MAP_SIDE.
+  fn size(&self, s: &str, column: usize, field: &'static str) -> Result<u32, MapParseError> {
+    match self.number(s, column, field)? {
+      n @ 1..=MAX_MAP_SIDE => Ok(n),
+      _ => Err(self.error(
+        column,
+        MapParseErrorKind::BadNumber {
+          field,
+          found: s.to_string(),
+        },
+      )),
+    }
+  }
+
+  // Reads HEIGHT rows of WIDTH glyphs, turning each into a tile with legend.
+  fn tiles(
+    &mut self,
+    width: u32,
+    height: u32,
+    legend: &HashMap<char, GridTile>,
+  ) -> Result<Vec<GridTile>, MapParseError> {
+    let mut grid_tiles = vec![];
+    for _ in 0..height {
+      let row = self.next("a row of tiles")?;
+      for (i, c) in row.chars().enumerate() {
+        let tile = legend
+          .get(&c)
+          .ok_or(self.error(i + 1, MapParseErrorKind::UnknownGlyph(c)))?;
+        grid_tiles.push(*tile);
+      }
+      // Count glyphs, not bytes, so that glyphs outside ASCII work.
+      let row_len = row.chars().count();
+      if row_len != width as usize {
+        return Err(self.error(
+          usize::min(row_len, width as usize) + 1,
+          MapParseErrorKind::RowLength {
+            expected: width,
+            found: row_len,
+          },
+        ));
+      }
+    }
+    Ok(grid_tiles)
+  }
+
+  // Checks that there's nothing left but blank lines.
+  fn end(&mut self) -> Result<(), MapParseError> {
+    for line in self.lines.by_ref() {
+      self.line_no += 1;
+      if !line.trim().is_empty() {
+        return Err(MapParseError {
+          line: self.line_no,
+          column: 1,
+          kind: MapParseErrorKind::Trailing(line.to_string()),
+        });
+      }
+    }
+    Ok(())
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum GridTile {
   Empty,
   Obstacle,
+  // Blocks units, but bullets fly over it.
+  Water,
+  // Slows units down.
+  Mud,
 }
 
-#[derive(Eq, PartialEq, Hash, Clone, Copy)]
+impl GridTile {
+  pub fn blocks_movement(self) -> bool {
+    match self {
+      GridTile::Obstacle | GridTile::Water => true,
+      GridTile::Empty | GridTile::Mud => false,
+    }
+  }
+
+  pub fn blocks_bullets(self) -> bool {
+    self == GridTile::Obstacle
+  }
+
+  // How fast units move on this tile, as a fraction of their usual speed.
+  pub fn speed_factor(self) -> f32 {
+    match self {
+      GridTile::Mud => 0.5,
+      _ => 1.,
+    }
+  }
+
+  // The tile's name in map files.
+  pub fn name(self) -> &'static str {
+    match self {
+      GridTile::Empty => "floor",
+      GridTile::Obstacle => "wall",
+      GridTile::Water => "water",
+      GridTile::Mud => "mud",
+    }
+  }
+
+  // The tile's glyph in LEGEND.
+  fn glyph(self) -> char {
+    LEGEND.iter().find(|(_, t)| *t == self).unwrap().0
+  }
+}
+
+impl FromStr for GridTile {
+  type Err = String;
+
+  // Parses a tile name, as given by GridTile::name.
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name {
+      "floor" => Ok(GridTile::Empty),
+      "wall" => Ok(GridTile::Obstacle),
+      "water" => Ok(GridTile::Water),
+      "mud" => Ok(GridTile::Mud),
+      other => Err(format!("unknown tile \"{}\"", other)),
+    }
+  }
+}
+
+// Entities are written one to a line with spaces between their parts, so
+// their names can't be empty or have spaces.
+fn check_name(what: &str, name: &str) -> Result<(), String> {
+  if name.is_empty() || name.contains(char::is_whitespace) {
+    return Err(format!(
+      "{} \"{}\" must not be empty or have spaces",
+      what, name
+    ));
+  }
+  Ok(())
+}
+
+// Something placed on the map, as read from a map file.
+pub enum MapEntity {
+  PlayerSpawn(TilePoint),
+  Spawner(Spawner),
+  Pickup(PickupSpawn),
+  Region(Region),
+}
+
+impl FromStr for MapEntity {
+  type Err = String;
+
+  // Parses a string of one of the forms:
+  // player_spawn X Y
+  // spawner KIND X Y
+  // pickup KIND X Y
+  // region NAME X Y WIDTH HEIGHT
+  //
+  // Player spawns are given to players in the order they appear. KIND and
+  // NAME must not have spaces.
+  fn from_str(line: &str) -> Result<Self, Self::Err> {
+    let elts: Vec<_> = line.split(' ').collect();
+    let num = |s: &str| u32::from_str(s).map_err(|e| format!("{:?}", e));
+    let wrong_len = || format!("entity \"{}\" has wrong number of elements", line);
+    match elts[0] {
+      "player_spawn" => {
+        if elts.len() != 3 {
+          return Err(wrong_len());
+        }
+        Ok(MapEntity::PlayerSpawn(TilePoint::new(
+          num(elts[1])?,
+          num(elts[2])?,
+        )))
+      }
+      "spawner" => {
+        if elts.len() != 4 {
+          return Err(wrong_len());
+        }
+        Ok(MapEntity::Spawner(Spawner {
+          kind: elts[1].to_string(),
+          pos: TilePoint::new(num(elts[2])?, num(elts[3])?),
+          properties: Properties::new(),
+        }))
+      }
+      "pickup" => {
+        if elts.len() != 4 {
+          return Err(wrong_len());
+        }
+        Ok(MapEntity::Pickup(PickupSpawn {
+          kind: elts[1].parse()?,
+          pos: TilePoint::new(num(elts[2])?, num(elts[3])?),
+          properties: Properties::new(),
+        }))
+      }
+      "region" => {
+        if elts.len() != 6 {
+          return Err(wrong_len());
+        }
+        Ok(MapEntity::Region(Region {
+          name: elts[1].to_string(),
+          top_left: TilePoint::new(num(elts[2])?, num(elts[3])?),
+          width: num(elts[4])?,
+          height: num(elts[5])?,
+          properties: Properties::new(),
+        }))
+      }
+      other => Err(format!("unknown entity \"{}\"", other)),
+    }
+  }
+}
+
+// Where enemies of some kind come from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spawner {
+  pub kind: String,
+  pub pos: TilePoint,
+  pub properties: Properties,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PickupSpawn {
+  pub kind: PickupKind,
+  pub pos: TilePoint,
+  pub properties: Properties,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PickupKind {
+  Health,
+}
+
+impl PickupKind {
+  // The pickup's name in map files.
+  pub fn name(self) -> &'static str {
+    match self {
+      PickupKind::Health => "health",
+    }
+  }
+}
+
+impl FromStr for PickupKind {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name {
+      "health" => Ok(PickupKind::Health),
+      other => Err(format!("unknown pickup \"{}\"", other)),
+    }
+  }
+}
+
+// A named rectangle of tiles, for scripting and level logic to refer to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+  pub name: String,
+  pub top_left: TilePoint,
+  // Measured in tiles.
+  pub width: u32,
+  pub height: u32,
+  pub properties: Properties,
+}
+
+impl Region {
+  pub fn contains(&self, p: TilePoint) -> bool {
+    p.x >= self.top_left.x
+      && p.y >= self.top_left.y
+      && (p.x as u64) < self.top_left.x as u64 + self.width as u64
+      && (p.y as u64) < self.top_left.y as u64 + self.height as u64
+  }
+
+  // Whether the region lies within a map of the given size. Sums are done in
+  // u64, as the region's numbers come straight from the file.
+  fn fits_in(&self, width: u32, height: u32) -> bool {
+    self.top_left.x as u64 + self.width as u64 <= width as u64
+      && self.top_left.y as u64 + self.height as u64 <= height as u64
+  }
+}
+
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
 pub struct TilePoint {
   x: u32,
   y: u32,
@@ -290,6 +1122,13 @@ impl TilePoint {
     TilePoint { x, y }
   }
 
+  pub fn x(self) -> u32 {
+    self.x
+  }
+  pub fn y(self) -> u32 {
+    self.y
+  }
+
   // TODO: Optimize to be a custom iterator. That way no malloc needed.
   pub fn neighbors4(&self, map: &Map) -> Vec<TilePoint> {
     let (x, y) = (self.x, self.y);
@@ -325,6 +1164,15 @@ impl TilePoint {
     }
   }
 
+  // The world-space square covered by this tile.
+  pub fn to_world_rect(self) -> Rect {
+    Rect {
+      top_left: self.to_world_point(),
+      width: Coord(TILE_WIDTH_F32),
+      height: Coord(TILE_WIDTH_F32),
+    }
+  }
+
   pub fn center_to_world_point(self) -> Point {
     Point {
       x: Coord((self.x as f32 + 0.5) * TILE_WIDTH_F32),
@@ -357,3 +1205,411 @@ impl Add for TilePoint {
     }
   }
 }
+
+// A map for tests in any module to share. It starts out as a copy of
+// media/test-map.txt, but is kept apart from it since the editor saves over
+// that one, and tests rely on where things are on this map.
+#[cfg(test)]
+pub const TEST_MAP: &str = include_str!("../media/fixture-map.txt");
+
+#[cfg(test)]
+pub fn test_map() -> Map {
+  TEST_MAP.parse().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const V1_MAP: &str = "3\n2\nXOX\nOOO\n";
+
+  fn pt(x: f32, y: f32) -> Point {
+    Point::new(Coord(x), Coord(y))
+  }
+
+  // A single wall, covering x and y from 64 up to but not including 128.
+  fn raycast_map() -> Map {
+    "4\n3\nOOOO\nOXOO\nOOOO\n".parse().unwrap()
+  }
+
+  fn wall_hit(x: f32, y: f32, distance: f32) -> Option<RayHit> {
+    Some(RayHit {
+      tile: TilePoint::new(1, 1),
+      point: pt(x, y),
+      distance: Coord(distance),
+    })
+  }
+
+  #[test]
+  fn raycast_hits_first_wall() {
+    let map = raycast_map();
+    assert_eq!(
+      map.raycast(pt(32., 96.), pt(224., 96.)),
+      wall_hit(64., 96., 32.)
+    );
+    assert_eq!(
+      map.raycast(pt(96., 224.), pt(96., 0.)),
+      wall_hit(96., 128., 96.)
+    );
+    assert_eq!(map.raycast(pt(0., 160.), pt(224., 140.)), None);
+    assert_eq!(map.raycast(pt(32., 32.), pt(32., 32.)), None);
+    // Starting in the wall.
+    assert_eq!(
+      map.raycast(pt(96., 96.), pt(200., 96.)),
+      wall_hit(96., 96., 0.)
+    );
+    assert!(!map.line_of_sight(pt(32., 96.), pt(224., 96.)));
+    assert!(map.line_of_sight(pt(32., 32.), pt(224., 32.)));
+  }
+
+  #[test]
+  fn raycast_ends_by_tile_inclusivity() {
+    let map = raycast_map();
+    // Left and top edges are in the wall...
+    assert_eq!(
+      map.raycast(pt(32., 96.), pt(64., 96.)),
+      wall_hit(64., 96., 32.)
+    );
+    assert_eq!(
+      map.raycast(pt(96., 0.), pt(96., 64.)),
+      wall_hit(96., 64., 64.)
+    );
+    // ...but right and bottom edges aren't.
+    assert_eq!(map.raycast(pt(192., 96.), pt(128., 96.)), None);
+    assert_eq!(map.raycast(pt(96., 192.), pt(96., 128.)), None);
+    assert_eq!(
+      map.raycast(pt(192., 96.), pt(127.5, 96.)),
+      wall_hit(128., 96., 64.)
+    );
+  }
+
+  #[test]
+  fn raycast_through_corners() {
+    let map = raycast_map();
+    let diagonal = 32. * 2f32.sqrt();
+    // Straight into the wall through its top left corner.
+    assert_eq!(
+      map.raycast(pt(32., 32.), pt(96., 96.)),
+      wall_hit(64., 64., diagonal)
+    );
+    // Past its top right corner, which belongs to the tile to the right.
+    assert_eq!(map.raycast(pt(96., 32.), pt(160., 96.)), None);
+    assert_eq!(map.raycast(pt(160., 96.), pt(96., 32.)), None);
+    // Past its bottom right corner, which belongs to the tile below right.
+    assert_eq!(map.raycast(pt(96., 160.), pt(160., 96.)), None);
+    // Through its bottom left corner, after which the ray is in the wall.
+    assert_eq!(
+      map.raycast(pt(32., 160.), pt(96., 96.)),
+      wall_hit(64., 128., diagonal)
+    );
+  }
+
+  #[test]
+  fn raycast_from_off_the_map() {
+    let map = raycast_map();
+    assert_eq!(
+      map.raycast(pt(-64., 96.), pt(100., 96.)),
+      wall_hit(64., 96., 128.)
+    );
+    assert_eq!(map.raycast(pt(-64., -64.), pt(500., -64.)), None);
+  }
+
+  // A small version 2 map, with the given tiles and entities.
+  fn v2_map(tiles: &str, entities: &str) -> String {
+    let n_entities = entities.lines().count();
+    format!(
+      "shooter-map 2\nsize 3 2\nlegend 3\nO floor\nX wall\n~ water\ntiles\n{}\nentities {}\n{}",
+      tiles, n_entities, entities
+    )
+  }
+
+  fn parse_err(s: &str) -> MapParseError {
+    match s.parse::<Map>() {
+      Ok(_) => panic!("map parsed, but it should have failed:\n{}", s),
+      Err(e) => e,
+    }
+  }
+
+  #[test]
+  fn parses_test_map() {
+    let map = test_map();
+    assert_eq!((map.width, map.height), (20, 12));
+    assert_eq!(map.grid_tiles.len(), 20 * 12);
+    assert_eq!(map.player_spawns.len(), 2);
+    assert!(map.region("spiral").is_some());
+  }
+
+  #[test]
+  fn parses_v1() {
+    let map: Map = V1_MAP.parse().unwrap();
+    assert_eq!((map.width, map.height), (3, 2));
+    assert_eq!(
+      map.grid_tiles,
+      vec![
+        GridTile::Obstacle,
+        GridTile::Empty,
+        GridTile::Obstacle,
+        GridTile::Empty,
+        GridTile::Empty,
+        GridTile::Empty,
+      ]
+    );
+  }
+
+  #[test]
+  fn parses_v2_entities() {
+    let map: Map = v2_map("O~X\nOOO", "player_spawn 0 1\nregion r 0 0 3 2\n")
+      .parse()
+      .unwrap();
+    assert_eq!(map.get_tile(TilePoint::new(1, 0)), Some(GridTile::Water));
+    assert_eq!(map.player_spawns, vec![TilePoint::new(0, 1)]);
+    assert!(map.region("r").unwrap().contains(TilePoint::new(2, 1)));
+  }
+
+  #[test]
+  fn bad_height_is_reported_as_height() {
+    let e = parse_err("3\nthree\nXOX\nOOO\n");
+    assert_eq!((e.line, e.column), (2, 1));
+    assert_eq!(
+      e.kind,
+      MapParseErrorKind::BadNumber {
+        field: "HEIGHT",
+        found: "three".to_string()
+      }
+    );
+    assert!(e.to_string().contains("HEIGHT"));
+  }
+
+  #[test]
+  fn rejects_zero_sizes() {
+    let e = parse_err("0\n2\n\n\n");
+    assert_eq!((e.line, e.column), (1, 1));
+    assert_eq!(
+      e.kind,
+      MapParseErrorKind::BadNumber {
+        field: "WIDTH",
+        found: "0".to_string()
+      }
+    );
+    let e = parse_err("3\n0\n");
+    assert_eq!((e.line, e.column), (2, 1));
+
+    let e = parse_err("shooter-map 2\nsize 0 2\nlegend 0\ntiles\n\n\nentities 0\n");
+    assert_eq!((e.line, e.column), (2, 6));
+    let e = parse_err("shooter-map 2\nsize 3 0\nlegend 0\ntiles\nentities 0\n");
+    assert_eq!((e.line, e.column), (2, 8));
+    assert_eq!(
+      e.kind,
+      MapParseErrorKind::BadNumber {
+        field: "HEIGHT",
+        found: "0".to_string()
+      }
+    );
+  }
+
+  #[test]
+  fn rejects_huge_sizes() {
+    let e = parse_err("100000\n100000\nX\n");
+    assert_eq!((e.line, e.column), (1, 1));
+    assert_eq!(
+      e.kind,
+      MapParseErrorKind::BadNumber {
+        field: "WIDTH",
+        found: "100000".to_string()
+      }
+    );
+    let e = parse_err(&format!("shooter-map 2\nsize 3 {}\n", MAX_MAP_SIDE + 1));
+    assert_eq!((e.line, e.column), (2, 8));
+
+    // The biggest allowed map only fails once it runs out of rows.
+    let e = parse_err(&format!("1\n{}\nX\n", MAX_MAP_SIDE));
+    assert_eq!(e.kind, MapParseErrorKind::Missing("a row of tiles"));
+  }
+
+  #[test]
+  fn rejects_unknown_glyphs() {
+    let e = parse_err("3\n2\nXOX\nO?O\n");
+    assert_eq!((e.line, e.column), (4, 2));
+    assert_eq!(e.kind, MapParseErrorKind::UnknownGlyph('?'));
+
+    // Glyphs that are fine in version 2 aren't in the version 1 legend.
+    let e = parse_err("3\n2\nXOX\nOO~\n");
+    assert_eq!((e.line, e.column), (4, 3));
+  }
+
+  #[test]
+  fn counts_row_length_in_chars() {
+    let map = "shooter-map 2\nsize 2 1\nlegend 2\n≈ water\nO floor\ntiles\n≈O\nentities 0\n";
+    let map: Map = map.parse().unwrap();
+    assert_eq!(map.grid_tiles, vec![GridTile::Water, GridTile::Empty]);
+  }
+
+  #[test]
+  fn rejects_short_and_long_rows() {
+    let e = parse_err("3\n2\nXO\nOOO\n");
+    assert_eq!((e.line, e.column), (3, 3));
+    assert_eq!(
+      e.kind,
+      MapParseErrorKind::RowLength {
+        expected: 3,
+        found: 2
+      }
+    );
+
+    let e = parse_err("3\n2\nXOX\nOOOO\n");
+    assert_eq!((e.line, e.column), (4, 4));
+  }
+
+  #[test]
+  fn rejects_trailing_rows() {
+    let e = parse_err("3\n2\nXOX\nOOO\nOOO\n");
+    assert_eq!((e.line, e.column), (5, 1));
+    assert_eq!(e.kind, MapParseErrorKind::Trailing("OOO".to_string()));
+
+    // Blank lines at the end are fine.
+    assert!("3\n2\nXOX\nOOO\n\n\n".parse::<Map>().is_ok());
+  }
+
+  #[test]
+  fn reports_missing_rows() {
+    let e = parse_err("3\n2\nXOX\n");
+    assert_eq!((e.line, e.column), (4, 1));
+    assert_eq!(e.kind, MapParseErrorKind::Missing("a row of tiles"));
+  }
+
+  #[test]
+  fn rejects_bad_entities() {
+    // Line 11 is the first entity.
+    let e = parse_err(&v2_map("OOO\nOOO", "player_spawn 5 0\n"));
+    assert_eq!((e.line, e.column), (11, 1));
+
+    let e = parse_err(&v2_map("O~O\nOOO", "player_spawn 1 0\n"));
+    assert_eq!((e.line, e.column), (11, 1));
+
+    let e = parse_err(&v2_map("OOO\nOOO", "teleporter 1 0\n"));
+    assert_eq!((e.line, e.column), (11, 1));
+  }
+
+  #[test]
+  fn rejects_regions_that_overflow() {
+    let e = parse_err(&v2_map("OOO\nOOO", "region r 4294967295 0 2 1\n"));
+    assert_eq!((e.line, e.column), (11, 1));
+    assert_eq!(
+      e.kind,
+      MapParseErrorKind::BadEntity("region \"r\" goes off the map".to_string())
+    );
+    let e = parse_err(&v2_map("OOO\nOOO", "region r 0 1 1 4294967295\n"));
+    assert_eq!((e.line, e.column), (11, 1));
+  }
+
+  #[test]
+  fn rejects_bad_legend() {
+    let e = parse_err("shooter-map 2\nsize 1 1\nlegend 2\nO floor\nO wall\ntiles\nO\nentities 0\n");
+    assert_eq!((e.line, e.column), (5, 1));
+    assert_eq!(e.kind, MapParseErrorKind::DuplicateGlyph('O'));
+
+    let e = parse_err("shooter-map 2\nsize 1 1\nlegend 1\nO lava\ntiles\nO\nentities 0\n");
+    assert_eq!((e.line, e.column), (4, 3));
+    assert_eq!(e.kind, MapParseErrorKind::UnknownTile("lava".to_string()));
+  }
+
+  #[test]
+  fn writes_test_map_exactly() {
+    assert_eq!(test_map().to_string(), TEST_MAP);
+  }
+
+  #[test]
+  fn round_trips_every_entity_and_tile() {
+    let mut map = Map::new(
+      4,
+      2,
+      vec![
+        GridTile::Empty,
+        GridTile::Obstacle,
+        GridTile::Water,
+        GridTile::Mud,
+        GridTile::Mud,
+        GridTile::Water,
+        GridTile::Obstacle,
+        GridTile::Empty,
+      ],
+    );
+    map.player_spawns = vec![TilePoint::new(3, 0), TilePoint::new(0, 0)];
+    map.spawners.push(Spawner {
+      kind: "grunt".to_string(),
+      pos: TilePoint::new(3, 1),
+      properties: Properties::new(),
+    });
+    map.pickups.push(PickupSpawn {
+      kind: PickupKind::Health,
+      pos: TilePoint::new(0, 1),
+      properties: Properties::new(),
+    });
+    map.regions.push(Region {
+      name: "all".to_string(),
+      top_left: TilePoint::new(0, 0),
+      width: 4,
+      height: 2,
+      properties: Properties::new(),
+    });
+
+    let file = map.to_string();
+    let read: Map = file.parse().unwrap();
+    assert_eq!(read, map);
+    assert_eq!(read.to_string(), file);
+  }
+
+  #[test]
+  fn only_saves_names_that_round_trip() {
+    let mut map: Map = V1_MAP.parse().unwrap();
+    map.regions.push(Region {
+      name: "the middle".to_string(),
+      top_left: TilePoint::new(1, 0),
+      width: 1,
+      height: 2,
+      properties: Properties::new(),
+    });
+    // It would be written, but couldn't be read back.
+    assert!(map.to_string().parse::<Map>().is_err());
+    // The check comes before anything's written, so the path doesn't matter.
+    assert_eq!(
+      map.save("/nonexistent/map.txt"),
+      Err("region name \"the middle\" must not be empty or have spaces".to_string())
+    );
+
+    map.regions[0].name = "the_middle".to_string();
+    let read: Map = map.to_string().parse().unwrap();
+    assert_eq!(read, map);
+  }
+
+  #[test]
+  fn rejects_empty_names() {
+    let e = parse_err(&v2_map("OOO\nOOO", "region  0 0 1 1\n"));
+    assert_eq!((e.line, e.column), (11, 1));
+    let e = parse_err(&v2_map("OOO\nOOO", "spawner  0 0\n"));
+    assert_eq!(
+      e.kind,
+      MapParseErrorKind::BadEntity(
+        "spawner kind \"\" must not be empty or have spaces".to_string()
+      )
+    );
+  }
+
+  #[test]
+  fn writes_v1_as_v2() {
+    let map: Map = V1_MAP.parse().unwrap();
+    let file = map.to_string();
+    assert!(file.starts_with(HEADER));
+    assert_eq!(file.parse::<Map>().unwrap(), map);
+  }
+
+  #[test]
+  fn rewrites_other_legends() {
+    let map: Map = "shooter-map 2\nsize 2 1\nlegend 2\n. floor\n# wall\ntiles\n.#\nentities 0\n"
+      .parse()
+      .unwrap();
+    let file = map.to_string();
+    assert!(file.contains("\ntiles\nOX\n"));
+    assert_eq!(file.parse::<Map>().unwrap(), map);
+  }
+}
@@ -0,0 +1,467 @@
+// Generates maps from a seed, so there's something to play besides the
+// handmade ones.
+//
+// Every style only uses floor and wall tiles, and walls in the whole border.
+// Whatever the style makes, every floor tile is then joined up with tunnels,
+// so all of the player spawns can reach each other. Spawns are spread out as
+// far from each other as the map allows.
+
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::map::{GridTile, Map, TilePoint};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Style {
+  // Caves grown with a cellular automaton: start from noise that's
+  // fill_percent walls, then smooth it smoothing_steps times.
+  Caves {
+    fill_percent: u32,
+    smoothing_steps: u32,
+  },
+  // Up to max_rooms rectangular rooms, with sides between min_room and
+  // max_room tiles, each joined to the last by a corridor.
+  Rooms {
+    max_rooms: u32,
+    min_room: u32,
+    max_room: u32,
+  },
+  // Caves that look the same when turned half way round, so that players
+  // starting on opposite spawns are evenly matched. Needs an even number of
+  // spawns.
+  SymmetricArena {
+    fill_percent: u32,
+    smoothing_steps: u32,
+  },
+}
+
+impl Style {
+  // The style with the usual settings, by name: "caves", "rooms" or "arena".
+  pub fn from_name(name: &str) -> Result<Style, String> {
+    match name {
+      "caves" => Ok(Style::Caves {
+        fill_percent: 45,
+        smoothing_steps: 5,
+      }),
+      "rooms" => Ok(Style::Rooms {
+        max_rooms: 12,
+        min_room: 3,
+        max_room: 8,
+      }),
+      "arena" => Ok(Style::SymmetricArena {
+        fill_percent: 38,
+        smoothing_steps: 4,
+      }),
+      other => Err(format!("unknown map style \"{}\"", other)),
+    }
+  }
+}
+
+pub struct Params {
+  pub style: Style,
+  // Measured in tiles, including the border.
+  pub width: u32,
+  pub height: u32,
+  pub n_player_spawns: usize,
+}
+
+// Generates a map. The same params and seed always give the same map.
+pub fn generate(params: &Params, seed: u64) -> Result<Map, String> {
+  let (width, height) = (params.width, params.height);
+  if width < 5 || height < 5 {
+    return Err(format!(
+      "map is {}x{}, but must be at least 5x5",
+      width, height
+    ));
+  }
+  let mut rng = StdRng::seed_from_u64(seed);
+  let mut map = Map::new(
+    width,
+    height,
+    vec![GridTile::Obstacle; (width * height) as usize],
+  );
+
+  let symmetric = match params.style {
+    Style::Caves {
+      fill_percent,
+      smoothing_steps,
+    } => {
+      caves(&mut map, &mut rng, fill_percent, smoothing_steps, false);
+      false
+    }
+    Style::Rooms {
+      max_rooms,
+      min_room,
+      max_room,
+    } => {
+      rooms(&mut map, &mut rng, max_rooms, min_room, max_room)?;
+      false
+    }
+    Style::SymmetricArena {
+      fill_percent,
+      smoothing_steps,
+    } => {
+      if params.n_player_spawns % 2 == 1 {
+        return Err(format!(
+          "symmetric arenas need an even number of player spawns, not {}",
+          params.n_player_spawns
+        ));
+      }
+      caves(&mut map, &mut rng, fill_percent, smoothing_steps, true);
+      true
+    }
+  };
+
+  connect_regions(&mut map, symmetric);
+  map.player_spawns = place_spawns(&map, &mut rng, params.n_player_spawns, symmetric)?;
+  Ok(map)
+}
+
+// Fills map with noise and smooths it into caves. If symmetric, the noise is
+// made symmetric, and smoothing keeps it that way since the rules treat every
+// direction alike.
+fn caves(
+  map: &mut Map,
+  rng: &mut StdRng,
+  fill_percent: u32,
+  smoothing_steps: u32,
+  symmetric: bool,
+) {
+  for p in interior(map) {
+    let wall = rng.gen_range(0..100) < fill_percent;
+    map.set_tile(
+      p,
+      if wall {
+        GridTile::Obstacle
+      } else {
+        GridTile::Empty
+      },
+    );
+  }
+  if symmetric {
+    for p in interior(map) {
+      let m = mirror(map, p);
+      // Copy the first half over the second.
+      if (p.y(), p.x()) > (m.y(), m.x()) {
+        let tile = map.get_tile(m).unwrap();
+        map.set_tile(p, tile);
+      }
+    }
+  }
+
+  for _ in 0..smoothing_steps {
+    let before = map.clone();
+    for p in interior(map) {
+      // The usual cave rule: tiles become walls when most of their neighbors
+      // are, and floor when most aren't.
+      let walls = walls_around(&before, p);
+      if walls > 4 {
+        map.set_tile(p, GridTile::Obstacle);
+      } else if walls < 4 {
+        map.set_tile(p, GridTile::Empty);
+      }
+    }
+  }
+}
+
+// Carves up to max_rooms non-overlapping rooms out of map, joining each to the
+// one before with an L-shaped corridor.
+fn rooms(
+  map: &mut Map,
+  rng: &mut StdRng,
+  max_rooms: u32,
+  min_room: u32,
+  max_room: u32,
+) -> Result<(), String> {
+  if min_room == 0 || min_room > max_room {
+    return Err(format!(
+      "room sizes {}..{} don't make sense",
+      min_room, max_room
+    ));
+  }
+  // Rooms as (x, y, width, height), in tiles.
+  let mut placed: Vec<(u32, u32, u32, u32)> = vec![];
+  for _ in 0..max_rooms {
+    let w = rng.gen_range(min_room..=max_room);
+    let h = rng.gen_range(min_room..=max_room);
+    // Leave the border, and don't let rooms touch without a corridor.
+    if w + 2 > map.width || h + 2 > map.height {
+      continue;
+    }
+    let x = rng.gen_range(1..=map.width - w - 1);
+    let y = rng.gen_range(1..=map.height - h - 1);
+    let overlaps = placed
+      .iter()
+      .any(|&(ox, oy, ow, oh)| x <= ox + ow && ox <= x + w && y <= oy + oh && oy <= y + h);
+    if overlaps {
+      continue;
+    }
+    for ry in y..y + h {
+      for rx in x..x + w {
+        map.set_tile(TilePoint::new(rx, ry), GridTile::Empty);
+      }
+    }
+    if let Some(&(px, py, pw, ph)) = placed.last() {
+      let from = TilePoint::new(px + pw / 2, py + ph / 2);
+      let to = TilePoint::new(x + w / 2, y + h / 2);
+      carve_corridor(map, from, to, rng.gen_bool(0.5), false);
+    }
+    placed.push((x, y, w, h));
+  }
+  if placed.is_empty() {
+    return Err(format!(
+      "no rooms fit on a {}x{} map",
+      map.width, map.height
+    ));
+  }
+  Ok(())
+}
+
+// Joins every floor tile up with the largest area of floor, by digging a
+// corridor from each other area to the nearest tile of the largest.
+fn connect_regions(map: &mut Map, symmetric: bool) {
+  loop {
+    let mut regions = regions(map);
+    if regions.len() <= 1 {
+      return;
+    }
+    regions.sort_by_key(|r| std::cmp::Reverse(r.len()));
+    // Digging only opens tiles, so each pass joins at least one area to the
+    // largest and this always finishes.
+    let from = regions[1][0];
+    let to = *regions[0]
+      .iter()
+      .min_by_key(|p| p.x().abs_diff(from.x()) + p.y().abs_diff(from.y()))
+      .unwrap();
+    carve_corridor(map, from, to, true, symmetric);
+  }
+}
+
+// Digs a corridor from a to b, going horizontally first if horizontal_first,
+// then vertically, or the other way round. If symmetric, the corridor is dug
+// again turned half way round.
+fn carve_corridor(
+  map: &mut Map,
+  a: TilePoint,
+  b: TilePoint,
+  horizontal_first: bool,
+  symmetric: bool,
+) {
+  let corner = if horizontal_first {
+    TilePoint::new(b.x(), a.y())
+  } else {
+    TilePoint::new(a.x(), b.y())
+  };
+  for (from, to) in [(a, corner), (corner, b)] {
+    for y in u32::min(from.y(), to.y())..=u32::max(from.y(), to.y()) {
+      for x in u32::min(from.x(), to.x())..=u32::max(from.x(), to.x()) {
+        let p = TilePoint::new(x, y);
+        map.set_tile(p, GridTile::Empty);
+        if symmetric {
+          map.set_tile(mirror(map, p), GridTile::Empty);
+        }
+      }
+    }
+  }
+}
+
+// Picks n spawns on floor tiles, each as far as it can be from the ones before
+// by walking distance. If symmetric, spawns come in pairs opposite each other.
+fn place_spawns(
+  map: &Map,
+  rng: &mut StdRng,
+  n: usize,
+  symmetric: bool,
+) -> Result<Vec<TilePoint>, String> {
+  let floor: Vec<_> = interior(map)
+    .filter(|p| map.get_tile(*p) == Some(GridTile::Empty))
+    .collect();
+  if floor.len() < n {
+    return Err(format!(
+      "map only has {} floor tiles, but needs {} player spawns",
+      floor.len(),
+      n
+    ));
+  }
+
+  let mut spawns = vec![];
+  while spawns.len() < n {
+    let p = if spawns.is_empty() {
+      floor[rng.gen_range(0..floor.len())]
+    } else {
+      let dist = distances_from(map, &spawns);
+      *floor.iter().max_by_key(|p| dist[index(map, **p)]).unwrap()
+    };
+    // Only possible once every floor tile has a spawn.
+    if spawns.contains(&p) {
+      return Err(format!("no room for {} player spawns", n));
+    }
+    spawns.push(p);
+    if symmetric {
+      let m = mirror(map, p);
+      // The middle tile is its own mirror, so it can't hold a pair.
+      if m == p {
+        return Err("no room for a pair of player spawns".to_string());
+      }
+      spawns.push(m);
+    }
+  }
+  Ok(spawns)
+}
+
+// Splits the floor into areas connected by neighbors4 steps.
+fn regions(map: &Map) -> Vec<Vec<TilePoint>> {
+  let mut seen = vec![false; map.grid_tiles.len()];
+  let mut regions = vec![];
+  for start in interior(map) {
+    if seen[index(map, start)] || map.get_tile(start) != Some(GridTile::Empty) {
+      continue;
+    }
+    let mut region = vec![];
+    let mut queue = VecDeque::from([start]);
+    seen[index(map, start)] = true;
+    while let Some(p) = queue.pop_front() {
+      region.push(p);
+      for n in p.neighbors4(map) {
+        if !seen[index(map, n)] && map.get_tile(n) == Some(GridTile::Empty) {
+          seen[index(map, n)] = true;
+          queue.push_back(n);
+        }
+      }
+    }
+    regions.push(region);
+  }
+  regions
+}
+
+// Walking distance from the nearest of starts to each tile, by index, or
+// u32::MAX for tiles that can't be reached.
+fn distances_from(map: &Map, starts: &[TilePoint]) -> Vec<u32> {
+  let mut dist = vec![u32::MAX; map.grid_tiles.len()];
+  let mut queue = VecDeque::new();
+  for p in starts.iter() {
+    dist[index(map, *p)] = 0;
+    queue.push_back(*p);
+  }
+  while let Some(p) = queue.pop_front() {
+    let d = dist[index(map, p)];
+    for n in p.neighbors4(map) {
+      if dist[index(map, n)] == u32::MAX && map.get_tile(n) == Some(GridTile::Empty) {
+        dist[index(map, n)] = d + 1;
+        queue.push_back(n);
+      }
+    }
+  }
+  // Unreachable tiles mustn't look like the farthest ones.
+  for d in dist.iter_mut() {
+    if *d == u32::MAX {
+      *d = 0;
+    }
+  }
+  dist
+}
+
+// How many of the 8 tiles around p are walls, counting off the map as wall.
+fn walls_around(map: &Map, p: TilePoint) -> u32 {
+  let mut walls = 0;
+  for dy in -1..=1i64 {
+    for dx in -1..=1i64 {
+      if dx == 0 && dy == 0 {
+        continue;
+      }
+      let (x, y) = (p.x() as i64 + dx, p.y() as i64 + dy);
+      let tile = if x < 0 || y < 0 {
+        None
+      } else {
+        map.get_tile(TilePoint::new(x as u32, y as u32))
+      };
+      if tile.map(|t| t.blocks_movement()).unwrap_or(true) {
+        walls += 1;
+      }
+    }
+  }
+  walls
+}
+
+// Every tile but the border.
+fn interior(map: &Map) -> impl Iterator<Item = TilePoint> {
+  let (width, height) = (map.width, map.height);
+  (1..height - 1).flat_map(move |y| (1..width - 1).map(move |x| TilePoint::new(x, y)))
+}
+
+// The tile opposite p, turning the map half way round its center.
+fn mirror(map: &Map, p: TilePoint) -> TilePoint {
+  TilePoint::new(map.width - 1 - p.x(), map.height - 1 - p.y())
+}
+
+fn index(map: &Map, p: TilePoint) -> usize {
+  (p.x() + p.y() * map.width) as usize
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn params(style: &str, n_player_spawns: usize) -> Params {
+    Params {
+      style: Style::from_name(style).unwrap(),
+      width: 40,
+      height: 30,
+      n_player_spawns,
+    }
+  }
+
+  #[test]
+  fn spawns_can_reach_each_other() {
+    for style in ["caves", "rooms", "arena"] {
+      for seed in 0..20 {
+        let map = generate(&params(style, 4), seed).unwrap();
+        assert_eq!(map.player_spawns.len(), 4);
+        let dist = distances_from(&map, &map.player_spawns[..1]);
+        for spawn in map.player_spawns.iter().skip(1) {
+          assert!(
+            dist[index(&map, *spawn)] > 0,
+            "{} map from seed {} has an unreachable spawn:\n{}",
+            style,
+            seed,
+            map
+          );
+        }
+        // Generated maps always have just the one area of floor.
+        assert_eq!(regions(&map).len(), 1);
+      }
+    }
+  }
+
+  #[test]
+  fn same_seed_gives_same_map() {
+    for style in ["caves", "rooms", "arena"] {
+      let a = generate(&params(style, 2), 42).unwrap();
+      let b = generate(&params(style, 2), 42).unwrap();
+      assert_eq!(a, b);
+      let c = generate(&params(style, 2), 43).unwrap();
+      assert_ne!(a, c);
+    }
+  }
+
+  #[test]
+  fn arenas_are_symmetric() {
+    for seed in 0..10 {
+      let map = generate(&params("arena", 2), seed).unwrap();
+      for (p, tile) in map.tiles().map(|t| (t.pos, t.tile)) {
+        assert_eq!(map.get_tile(mirror(&map, p)), Some(tile));
+      }
+      assert_eq!(map.player_spawns[1], mirror(&map, map.player_spawns[0]));
+    }
+  }
+
+  #[test]
+  fn rejects_bad_params() {
+    assert!(generate(&params("arena", 3), 0).is_err());
+    let mut tiny = params("caves", 2);
+    tiny.width = 4;
+    assert!(generate(&tiny, 0).is_err());
+  }
+}
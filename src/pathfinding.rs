@@ -0,0 +1,219 @@
+// A* pathfinding over the tiles of a map.
+//
+// Paths move between tiles in any of 8 directions, but never cut a corner: a
+// diagonal step is only allowed when both of the tiles beside it are open too,
+// since a unit would otherwise clip the wall it's squeezing past. Tiles that
+// slow units down cost more to cross, so paths go around mud when that's
+// quicker.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::map::{GridTile, Map, TilePoint};
+
+// Costs of a step, scaled so that diagonals stay close to √2 in integers.
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+// Returns the cheapest path from start to goal, including both, or None if
+// there isn't one. Either end being blocked or off the map means no path.
+pub fn find_path(map: &Map, start: TilePoint, goal: TilePoint) -> Option<Vec<TilePoint>> {
+  if !is_open(map, start) || !is_open(map, goal) {
+    return None;
+  }
+
+  let n_tiles = map.grid_tiles.len();
+  let mut cost = vec![u32::MAX; n_tiles];
+  let mut came_from: Vec<Option<TilePoint>> = vec![None; n_tiles];
+  // Ordered by estimated total cost, then by the order tiles were added, so
+  // that ties always break the same way.
+  let mut open = BinaryHeap::new();
+  let mut n_pushed = 0u32;
+
+  cost[index(map, start)] = 0;
+  open.push(Reverse((
+    heuristic(start, goal),
+    n_pushed,
+    start.x(),
+    start.y(),
+  )));
+  while let Some(Reverse((_, _, x, y))) = open.pop() {
+    let p = TilePoint::new(x, y);
+    if p == goal {
+      return Some(reconstruct(map, &came_from, goal));
+    }
+    let p_cost = cost[index(map, p)];
+    for (next, step_cost) in steps(map, p) {
+      let next_cost = p_cost + step_cost;
+      if next_cost < cost[index(map, next)] {
+        cost[index(map, next)] = next_cost;
+        came_from[index(map, next)] = Some(p);
+        n_pushed += 1;
+        open.push(Reverse((
+          next_cost + heuristic(next, goal),
+          n_pushed,
+          next.x(),
+          next.y(),
+        )));
+      }
+    }
+  }
+  None
+}
+
+// The tiles reachable in one step from p, with what each step costs.
+pub fn steps(map: &Map, p: TilePoint) -> impl Iterator<Item = (TilePoint, u32)> + '_ {
+  neighbors(map, p).map(move |next| (next, step_cost(map, p, next)))
+}
+
+// The open tiles around p that a unit can step to. Since corners are never
+// cut, q is a neighbor of p exactly when p is a neighbor of q.
+pub fn neighbors(map: &Map, p: TilePoint) -> impl Iterator<Item = TilePoint> + '_ {
+  const DIRS: [(i64, i64); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+  ];
+  DIRS.iter().filter_map(move |&(dx, dy)| {
+    let next = offset(map, p, dx, dy)?;
+    if !is_open(map, next) {
+      return None;
+    }
+    if dx != 0 && dy != 0 {
+      let beside_x = offset(map, p, dx, 0)?;
+      let beside_y = offset(map, p, 0, dy)?;
+      if !is_open(map, beside_x) || !is_open(map, beside_y) {
+        return None;
+      }
+    }
+    Some(next)
+  })
+}
+
+// What it costs to step from one tile to a neighbor, which depends on how
+// fast units cross the tile they step onto.
+pub fn step_cost(map: &Map, from: TilePoint, to: TilePoint) -> u32 {
+  let base = if from.x() != to.x() && from.y() != to.y() {
+    DIAGONAL_COST
+  } else {
+    STRAIGHT_COST
+  };
+  let speed = map.get_tile(to).map_or(1., GridTile::speed_factor);
+  (base as f32 / speed).round() as u32
+}
+
+// The octile distance: the cost of the path if nothing was in the way and
+// every tile was floor. It never overestimates, so A* still finds the best
+// path.
+fn heuristic(a: TilePoint, b: TilePoint) -> u32 {
+  let dx = a.x().abs_diff(b.x());
+  let dy = a.y().abs_diff(b.y());
+  STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+}
+
+fn reconstruct(map: &Map, came_from: &[Option<TilePoint>], goal: TilePoint) -> Vec<TilePoint> {
+  let mut path = vec![goal];
+  while let Some(prev) = came_from[index(map, *path.last().unwrap())] {
+    path.push(prev);
+  }
+  path.reverse();
+  path
+}
+
+// Whether units can stand on the tile at p.
+pub fn is_open(map: &Map, p: TilePoint) -> bool {
+  map.get_tile(p).is_some_and(|t| !t.blocks_movement())
+}
+
+// The tile at p + (dx, dy), if that's on the map.
+fn offset(map: &Map, p: TilePoint, dx: i64, dy: i64) -> Option<TilePoint> {
+  let (x, y) = (p.x() as i64 + dx, p.y() as i64 + dy);
+  if x < 0 || y < 0 || x >= map.width as i64 || y >= map.height as i64 {
+    return None;
+  }
+  Some(TilePoint::new(x as u32, y as u32))
+}
+
+fn index(map: &Map, p: TilePoint) -> usize {
+  (p.x() + p.y() * map.width) as usize
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::test_map;
+
+  fn p(x: u32, y: u32) -> TilePoint {
+    TilePoint::new(x, y)
+  }
+
+  // Checks that every step of path is to an open neighbor, without cutting
+  // corners.
+  fn check_steps(map: &Map, path: &[TilePoint]) {
+    for pair in path.windows(2) {
+      let (a, b) = (pair[0], pair[1]);
+      assert!(
+        steps(map, a).any(|(next, _)| next == b),
+        "bad step from {:?} to {:?}",
+        a,
+        b
+      );
+    }
+  }
+
+  #[test]
+  fn finds_way_into_spiral() {
+    let map = test_map();
+    let path = find_path(&map, p(1, 1), p(9, 7)).unwrap();
+    check_steps(&map, &path);
+    assert_eq!(path[0], p(1, 1));
+    // The only way in is round the inside of the spiral.
+    assert!(path.ends_with(&[p(11, 4), p(11, 5), p(11, 6), p(11, 7), p(10, 7), p(9, 7)]));
+    // 3 diagonal steps and 7 straight ones get to (11, 4).
+    assert_eq!(path.len(), 16);
+  }
+
+  #[test]
+  fn finds_trivial_path() {
+    let map = test_map();
+    assert_eq!(find_path(&map, p(1, 1), p(1, 1)), Some(vec![p(1, 1)]));
+  }
+
+  #[test]
+  fn no_path_to_blocked_tiles() {
+    let map = test_map();
+    // A wall of the spiral, the pond, and off the map.
+    assert_eq!(find_path(&map, p(1, 1), p(7, 3)), None);
+    assert_eq!(find_path(&map, p(1, 1), p(16, 5)), None);
+    assert_eq!(find_path(&map, p(1, 1), p(20, 0)), None);
+  }
+
+  #[test]
+  fn does_not_cut_corners() {
+    let map: Map = "2\n2\nOX\nXO\n".parse().unwrap();
+    assert_eq!(find_path(&map, p(0, 0), p(1, 1)), None);
+
+    let map: Map = "2\n2\nOO\nXO\n".parse().unwrap();
+    assert_eq!(
+      find_path(&map, p(0, 0), p(1, 1)),
+      Some(vec![p(0, 0), p(1, 0), p(1, 1)])
+    );
+  }
+
+  #[test]
+  fn goes_around_mud() {
+    let map: Map =
+      "shooter-map 2\nsize 3 2\nlegend 2\nO floor\n, mud\ntiles\nO,O\nOOO\nentities 0\n"
+        .parse()
+        .unwrap();
+    assert_eq!(
+      find_path(&map, p(0, 0), p(2, 0)),
+      Some(vec![p(0, 0), p(1, 1), p(2, 0)])
+    );
+  }
+}
@@ -0,0 +1,299 @@
+// Recording and playback of games.
+//
+// A replay holds everything needed to reproduce a match exactly: the map, the
+// RNG seed, and every player's command on every tick. Playing it back should
+// reach the same state, which we check with game::State::checksum.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::dimensions::{WorldCoord as Coord, WorldPoint as Point};
+use crate::game::{self, PlayerCommand};
+
+const HEADER: &str = "shooter-replay 1";
+
+pub struct Replay {
+  pub map_path: String,
+  pub seed: u64,
+  // ticks[t][i] is the command given to player i on tick t.
+  pub ticks: Vec<Vec<PlayerCommand>>,
+  // Checksum of the game state after the last tick. None until the recording
+  // is finished.
+  pub checksum: Option<u64>,
+}
+
+impl Replay {
+  pub fn new(map_path: &str, seed: u64) -> Replay {
+    Replay {
+      map_path: map_path.to_string(),
+      seed,
+      ticks: vec![],
+      checksum: None,
+    }
+  }
+
+  // Call this with the commands for each tick, just before running it.
+  pub fn record(&mut self, commands: &[PlayerCommand]) {
+    self.ticks.push(commands.to_vec());
+  }
+
+  pub fn finish(&mut self, game: &game::State) {
+    self.checksum = Some(game.checksum());
+  }
+
+  // Plays the replay from the start, returning the final game state.
+  pub fn play(&self) -> Result<game::State, String> {
+    let mut game = game::State::level1(&self.map_path, self.seed)?;
+    for commands in self.ticks.iter() {
+      game.tick(commands);
+    }
+    Ok(game)
+  }
+
+  // Writes the replay to a file.
+  //
+  // The format is like so:
+  // shooter-replay 1
+  // map MAP_PATH
+  // seed SEED
+  // ticks N_TICKS
+  // COMMANDS (one line per tick)
+  // checksum CHECKSUM
+  //
+  // Each line of COMMANDS has one command per player, separated by " | ".
+  // A command is "MOVE_X MOVE_Y AIM_X AIM_Y FIRE", where AIM_X and AIM_Y are
+  // both "-" if the player isn't aiming, and FIRE is 0 or 1.
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", HEADER);
+    let _ = writeln!(out, "map {}", self.map_path);
+    let _ = writeln!(out, "seed {}", self.seed);
+    let _ = writeln!(out, "ticks {}", self.ticks.len());
+    for commands in self.ticks.iter() {
+      let line: Vec<String> = commands.iter().map(format_command).collect();
+      let _ = writeln!(out, "{}", line.join(" | "));
+    }
+    if let Some(checksum) = self.checksum {
+      let _ = writeln!(out, "checksum {:016x}", checksum);
+    }
+    std::fs::write(path, out).map_err(|e| format!("err writing file: {:?}", e))
+  }
+
+  // Reads a replay written by Replay::save.
+  pub fn from_file(path: &str) -> Result<Replay, String> {
+    let file = std::fs::read_to_string(path).map_err(|e| format!("err reading file: {:?}", e))?;
+    let mut lines = file.lines();
+
+    if lines.next() != Some(HEADER) {
+      return Err(format!("replay doesn't start with \"{}\"", HEADER));
+    }
+    let map_path = field(lines.next(), "map")?.to_string();
+    let seed = u64::from_str(field(lines.next(), "seed")?)
+      .map_err(|e| format!("err parsing seed: {:?}", e))?;
+    let n_ticks = usize::from_str(field(lines.next(), "ticks")?)
+      .map_err(|e| format!("err parsing ticks: {:?}", e))?;
+
+    let mut ticks = Vec::with_capacity(n_ticks);
+    for i in 0..n_ticks {
+      let line = lines
+        .next()
+        .ok_or(format!("replay ends at tick {} of {}", i, n_ticks))?;
+      let commands = line
+        .split(" | ")
+        .map(parse_command)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("tick {}: {}", i, e))?;
+      ticks.push(commands);
+    }
+
+    let checksum = match lines.next() {
+      None => None,
+      Some(line) => Some(
+        u64::from_str_radix(field(Some(line), "checksum")?, 16)
+          .map_err(|e| format!("err parsing checksum: {:?}", e))?,
+      ),
+    };
+
+    Ok(Replay {
+      map_path,
+      seed,
+      ticks,
+      checksum,
+    })
+  }
+}
+
+// Plays back the replay at path, printing a summary and whether the final
+// state matched the recording.
+pub fn run(path: &str) -> Result<(), String> {
+  let replay = Replay::from_file(path)?;
+  let game = replay.play()?;
+  crate::headless::print_summary(&game);
+
+  let actual = game.checksum();
+  match replay.checksum {
+    None => {
+      println!(
+        "checksum {:016x} (replay has none to compare against)",
+        actual
+      );
+      Ok(())
+    }
+    Some(expected) if expected == actual => {
+      println!("checksum {:016x} matches", actual);
+      Ok(())
+    }
+    Some(expected) => Err(format!(
+      "desync: expected checksum {:016x}, got {:016x}",
+      expected, actual
+    )),
+  }
+}
+
+// Strips "NAME " from the front of a "NAME VALUE" line.
+fn field<'a>(line: Option<&'a str>, name: &str) -> Result<&'a str, String> {
+  line
+    .and_then(|l| l.strip_prefix(name))
+    .and_then(|l| l.strip_prefix(' '))
+    .ok_or(format!("replay missing {}", name))
+}
+
+fn format_command(command: &PlayerCommand) -> String {
+  // f32's Display prints the shortest string that parses back to the same
+  // value, so this round-trips exactly.
+  let aim = match command.aim_at {
+    Some(p) => format!("{} {}", p.x.0, p.y.0),
+    None => "- -".to_string(),
+  };
+  format!(
+    "{} {} {} {}",
+    command.move_dir.x.0, command.move_dir.y.0, aim, command.shooting as u8
+  )
+}
+
+fn parse_command(s: &str) -> Result<PlayerCommand, String> {
+  let elts: Vec<_> = s.split(' ').collect();
+  if elts.len() != 5 {
+    return Err(format!("command \"{}\" has wrong number of elements", s));
+  }
+  let num = |s: &str| f32::from_str(s).map_err(|e| format!("{:?}", e));
+  let aim_at = match (elts[2], elts[3]) {
+    ("-", "-") => None,
+    (x, y) => Some(Point::new(Coord(num(x)?), Coord(num(y)?))),
+  };
+  let shooting = match elts[4] {
+    "0" => false,
+    "1" => true,
+    other => return Err(format!("bad FIRE \"{}\"", other)),
+  };
+  Ok(PlayerCommand {
+    move_dir: Point::new(Coord(num(elts[0])?), Coord(num(elts[1])?)),
+    aim_at,
+    shooting,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::TEST_MAP;
+
+  // A directory of its own in the temp dir for one test, removed along with
+  // everything in it when dropped, even if the test fails.
+  struct TempDir(std::path::PathBuf);
+
+  impl TempDir {
+    fn new(test: &str) -> TempDir {
+      let dir = format!("shooter-replay-test-{}-{}", std::process::id(), test);
+      let path = std::env::temp_dir().join(dir);
+      std::fs::create_dir_all(&path).unwrap();
+      TempDir(path)
+    }
+
+    fn path(&self, file: &str) -> String {
+      self.0.join(file).to_str().unwrap().to_string()
+    }
+
+    // Writes the test map into the dir, so a replay can load it by path.
+    fn test_map(&self) -> String {
+      let path = self.path("map.txt");
+      std::fs::write(&path, TEST_MAP).unwrap();
+      path
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.0);
+    }
+  }
+
+  // Some made-up input for player i on tick t, with awkward floats so that
+  // saving has to round-trip them exactly.
+  fn command(t: usize, i: usize) -> PlayerCommand {
+    let x = t as f32;
+    PlayerCommand {
+      move_dir: Point::new(Coord((x * 0.1).sin()), Coord(i as f32 - 0.5)),
+      aim_at: match t % 3 {
+        0 => None,
+        _ => Some(Point::new(Coord(x * 1.7), Coord(100.3))),
+      },
+      shooting: t % 4 < 2,
+    }
+  }
+
+  // Records n_ticks of a game on the map at map_path.
+  fn record(map_path: &str, seed: u64, n_ticks: usize) -> Replay {
+    let mut replay = Replay::new(map_path, seed);
+    let mut game = game::State::level1(map_path, seed).unwrap();
+    for t in 0..n_ticks {
+      let commands: Vec<_> = (0..game.players.len()).map(|i| command(t, i)).collect();
+      replay.record(&commands);
+      game.tick(&commands);
+    }
+    replay.finish(&game);
+    replay
+  }
+
+  #[test]
+  fn saves_and_loads() {
+    let dir = TempDir::new("saves_and_loads");
+    let map_path = dir.test_map();
+    let replay = record(&map_path, 7, 50);
+    let path = dir.path("test.replay");
+    replay.save(&path).unwrap();
+    let loaded = Replay::from_file(&path).unwrap();
+
+    assert_eq!(loaded.map_path, replay.map_path);
+    assert_eq!(loaded.seed, 7);
+    assert_eq!(loaded.checksum, replay.checksum);
+    assert_eq!(loaded.ticks.len(), 50);
+    for (a, b) in loaded.ticks.iter().zip(replay.ticks.iter()) {
+      assert_eq!(format!("{:?}", a), format!("{:?}", b));
+    }
+  }
+
+  #[test]
+  fn plays_back_to_the_same_checksum() {
+    let dir = TempDir::new("plays_back");
+    let map_path = dir.test_map();
+    let replay = record(&map_path, 3, 300);
+    let played = replay.play().unwrap();
+    assert_eq!(Some(played.checksum()), replay.checksum);
+    assert_eq!(played.checksum(), replay.play().unwrap().checksum());
+  }
+
+  #[test]
+  fn run_reports_desyncs() {
+    let dir = TempDir::new("run_reports_desyncs");
+    let map_path = dir.test_map();
+    let mut replay = record(&map_path, 0, 100);
+    let path = dir.path("test.replay");
+    replay.save(&path).unwrap();
+    assert_eq!(run(&path), Ok(()));
+
+    replay.checksum = replay.checksum.map(|c| c ^ 1);
+    replay.save(&path).unwrap();
+    assert!(run(&path).unwrap_err().starts_with("desync: "));
+  }
+}
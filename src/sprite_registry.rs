@@ -0,0 +1,336 @@
+// All of the game's sprites, from any number of sprite sheets.
+//
+// Sprites can come from .sps sheets, Aseprite .json exports, or loose .png
+// files. Loose images are packed together into an atlas texture at startup,
+// so they cost no more to draw than sprites from a sheet. Sprite names are
+// shared by all of them, so a name can only be used once across every source.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use sdl2::image::LoadSurface;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, RenderTarget, TextureCreator};
+use sdl2::surface::Surface;
+use sdl2::video::WindowContext;
+
+use crate::sprite_sheet::{Animation, SpriteHandle, SpriteKey, SpriteRef, SpriteSheet};
+
+// Loose images are packed into an atlas this wide, or as wide as the widest
+// image if that's wider.
+const ATLAS_WIDTH: u32 = 1024;
+// Gap left between packed images, so that scaling doesn't bleed one into the
+// next.
+const ATLAS_PADDING: u32 = 1;
+
+pub struct SpriteRegistry<'texture> {
+  pub sheets: Vec<SpriteSheet<'texture>>,
+  index: HashMap<String, SpriteHandle>,
+}
+
+impl<'texture> SpriteRegistry<'texture> {
+  // Loads sprites from each of paths, which can be:
+  // - a sprite sheet, read by SpriteSheet::from_file,
+  // - a .png file, which becomes one sprite named after the file, or
+  // - a directory, in which each .png file becomes a sprite as above.
+  //
+  // Fails if any sprite name is used more than once, listing every clash.
+  pub fn from_paths<'txc>(
+    paths: &[&str],
+    texture_creator: &'txc TextureCreator<WindowContext>,
+  ) -> Result<SpriteRegistry<'txc>, String> {
+    let mut sheets = vec![];
+    // Every sprite's name, with the file it came from.
+    let mut names: Vec<(String, String)> = vec![];
+    let mut images = vec![];
+    for path in paths.iter() {
+      if Path::new(path).is_dir() {
+        images.extend(pngs_in_dir(path)?);
+      } else if path.ends_with(".png") {
+        images.push(path.to_string());
+      } else {
+        let sheet = SpriteSheet::from_file(sheets.len(), path, texture_creator)
+          .map_err(|e| format!("error loading sprite sheet \"{}\": {}", path, e))?;
+        for sprite_ref in sheet.sprite_map.iter() {
+          names.push((sprite_ref.name.clone(), path.to_string()));
+        }
+        sheets.push(sheet);
+      }
+    }
+    let image_names = images
+      .iter()
+      .map(|path| image_sprite_name(path))
+      .collect::<Result<Vec<_>, _>>()?;
+    names.extend(image_names.iter().cloned().zip(images.iter().cloned()));
+
+    check_names(&names)?;
+
+    if !images.is_empty() {
+      let atlas = pack_atlas(sheets.len(), &image_names, &images, texture_creator)?;
+      sheets.push(atlas);
+    }
+
+    let mut index = HashMap::new();
+    for sheet in sheets.iter() {
+      for (name, handle) in sheet.sprites() {
+        index.insert(name.to_string(), handle);
+      }
+    }
+
+    Ok(SpriteRegistry { sheets, index })
+  }
+
+  // Finds the named sprite, or returns an error if there's no such sprite.
+  pub fn sprite(&self, name: &str) -> Result<SpriteHandle, String> {
+    self
+      .index
+      .get(name)
+      .copied()
+      .ok_or(format!("sprite \"{}\" not found", name))
+  }
+
+  // Looks up the key's sprite and remembers its handle, so that later lookups
+  // through lookup() don't need to search.
+  pub fn resolve(&self, key: &mut SpriteKey) -> Result<SpriteHandle, String> {
+    let handle = self.sprite(&key.name)?;
+    key.handle = Some(handle);
+    Ok(handle)
+  }
+
+  // Returns the key's handle, from the key itself if it's been resolved.
+  pub fn lookup(&self, key: &SpriteKey) -> Option<SpriteHandle> {
+    key.handle.or_else(|| self.index.get(&key.name).copied())
+  }
+
+  // See SpriteSheet::animation_for.
+  pub fn animation_for(&self, sprite: SpriteHandle, suffix: &str) -> Option<&Animation> {
+    self.sheets[sprite.sheet].animation_for(sprite, suffix)
+  }
+
+  pub fn blit_sprite_to_rect<Ctx: RenderTarget>(
+    &self,
+    sprite: SpriteHandle,
+    canvas: &mut Canvas<Ctx>,
+    dst_rect: Rect,
+  ) -> Result<(), String> {
+    self.sheets[sprite.sheet].blit_sprite_to_rect(sprite, canvas, dst_rect)
+  }
+
+  pub fn blit_sprite_to_rect_rotated<Ctx: RenderTarget>(
+    &self,
+    sprite: SpriteHandle,
+    canvas: &mut Canvas<Ctx>,
+    dst_rect: Rect,
+    angle: f64,
+  ) -> Result<(), String> {
+    self.sheets[sprite.sheet].blit_sprite_to_rect_rotated(sprite, canvas, dst_rect, angle)
+  }
+}
+
+// Lists the .png files in dir, sorted so that loading is deterministic.
+fn pngs_in_dir(dir: &str) -> Result<Vec<String>, String> {
+  let entries =
+    std::fs::read_dir(dir).map_err(|e| format!("err reading dir \"{}\": {:?}", dir, e))?;
+  let mut pngs = vec![];
+  for entry in entries {
+    let path = entry
+      .map_err(|e| format!("err reading dir \"{}\": {:?}", dir, e))?
+      .path();
+    if path.extension().is_some_and(|ext| ext == "png") {
+      pngs.push(path.to_string_lossy().into_owned());
+    }
+  }
+  pngs.sort();
+  Ok(pngs)
+}
+
+// A loose image's sprite is named after its file, minus the extension.
+fn image_sprite_name(path: &str) -> Result<String, String> {
+  let name = Path::new(path)
+    .file_stem()
+    .map(|s| s.to_string_lossy().into_owned())
+    .ok_or(format!("image \"{}\" has no file name", path))?;
+  if name.contains(' ') {
+    return Err(format!("image \"{}\" has a space in its name", path));
+  }
+  Ok(name)
+}
+
+// Checks that no sprite name is used twice, given every sprite's name with the
+// file it came from. The error lists every clash, one per line.
+fn check_names(names: &[(String, String)]) -> Result<(), String> {
+  let mut origins: HashMap<&str, &str> = HashMap::new();
+  let mut clashes = vec![];
+  for (name, origin) in names.iter() {
+    match origins.get(name.as_str()) {
+      Some(prev) => clashes.push(format!(
+        "sprite \"{}\" is in both \"{}\" and \"{}\"",
+        name, prev, origin
+      )),
+      None => {
+        origins.insert(name, origin);
+      }
+    }
+  }
+  if !clashes.is_empty() {
+    return Err(clashes.join("\n"));
+  }
+  Ok(())
+}
+
+// Packs the images at paths into one texture, and makes a sheet of it with a
+// sprite for each image, called by the matching entry in names.
+fn pack_atlas<'txc>(
+  id: usize,
+  names: &[String],
+  paths: &[String],
+  texture_creator: &'txc TextureCreator<WindowContext>,
+) -> Result<SpriteSheet<'txc>, String> {
+  let mut images = vec![];
+  for (name, path) in names.iter().zip(paths) {
+    let mut surface =
+      Surface::from_file(path).map_err(|e| format!("err loading \"{}\": {}", path, e))?;
+    // Copy the pixels as they are, alpha included, rather than blending them
+    // onto the atlas.
+    surface.set_blend_mode(BlendMode::None)?;
+    images.push((name, surface));
+  }
+
+  let sizes: Vec<_> = images
+    .iter()
+    .map(|(_, s)| (s.width(), s.height()))
+    .collect();
+  let (width, height, positions) = pack(&sizes);
+  let mut atlas = Surface::new(width, height, PixelFormatEnum::RGBA32)?;
+  let mut sprite_map = vec![];
+  for ((name, surface), (x, y)) in images.iter().zip(positions) {
+    let dst = Rect::new(x as i32, y as i32, surface.width(), surface.height());
+    surface.blit(None, &mut atlas, dst)?;
+    sprite_map.push(SpriteRef {
+      name: name.to_string(),
+      offset_x: x,
+      offset_y: y,
+      width: surface.width(),
+      height: surface.height(),
+    });
+  }
+
+  let texture = atlas
+    .as_texture(texture_creator)
+    .map_err(|e| format!("err making atlas texture: {:?}", e))?;
+  SpriteSheet::new(id, texture, sprite_map, vec![])
+}
+
+// Packs rectangles of the given sizes into shelves: rows filled left to right,
+// tallest first. Returns the total width and height, and where each rectangle
+// goes, in the same order as sizes.
+fn pack(sizes: &[(u32, u32)]) -> (u32, u32, Vec<(u32, u32)>) {
+  let widest = sizes.iter().map(|(w, _)| *w).max().unwrap_or(0);
+  let width = u32::max(ATLAS_WIDTH, widest);
+
+  let mut order: Vec<usize> = (0..sizes.len()).collect();
+  order.sort_by_key(|i| std::cmp::Reverse(sizes[*i].1));
+
+  let mut positions = vec![(0, 0); sizes.len()];
+  let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+  for i in order {
+    let (w, h) = sizes[i];
+    if x > 0 && x + w > width {
+      x = 0;
+      y += shelf_height + ATLAS_PADDING;
+      shelf_height = 0;
+    }
+    positions[i] = (x, y);
+    x += w + ATLAS_PADDING;
+    shelf_height = u32::max(shelf_height, h);
+  }
+  (width, u32::max(y + shelf_height, 1), positions)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn overlaps(a: (u32, u32, u32, u32), b: (u32, u32, u32, u32)) -> bool {
+    a.0 < b.0 + b.2 && b.0 < a.0 + a.2 && a.1 < b.1 + b.3 && b.1 < a.1 + a.3
+  }
+
+  // Packs sizes, and checks that every rectangle is inside the atlas and
+  // clear of the others. Returns the atlas size.
+  fn check_pack(sizes: &[(u32, u32)]) -> (u32, u32) {
+    let (width, height, positions) = pack(sizes);
+    assert_eq!(positions.len(), sizes.len());
+    let rects: Vec<_> = positions
+      .iter()
+      .zip(sizes)
+      .map(|(&(x, y), &(w, h))| (x, y, w, h))
+      .collect();
+    for (i, &a) in rects.iter().enumerate() {
+      assert!(
+        a.0 + a.2 <= width && a.1 + a.3 <= height,
+        "{:?} doesn't fit",
+        a
+      );
+      for &b in rects[i + 1..].iter() {
+        assert!(!overlaps(a, b), "{:?} overlaps {:?}", a, b);
+      }
+    }
+    (width, height)
+  }
+
+  #[test]
+  fn packs_without_overlap() {
+    let sizes: Vec<_> = (1..60)
+      .map(|i| (i * 7 % 200 + 1, i * 13 % 90 + 1))
+      .collect();
+    let (width, height) = check_pack(&sizes);
+    assert_eq!(width, ATLAS_WIDTH);
+    // Enough to need more than one shelf.
+    assert!(height > 90);
+  }
+
+  #[test]
+  fn packs_images_wider_than_the_atlas() {
+    let (width, _) = check_pack(&[(10, 10), (ATLAS_WIDTH + 5, 3), (ATLAS_WIDTH, 4)]);
+    assert_eq!(width, ATLAS_WIDTH + 5);
+  }
+
+  #[test]
+  fn packs_nothing() {
+    assert_eq!(pack(&[]), (ATLAS_WIDTH, 1, vec![]));
+    check_pack(&[(0, 0), (5, 0)]);
+  }
+
+  #[test]
+  fn reports_every_name_clash() {
+    let names: Vec<_> = [
+      ("guy", "a.sps"),
+      ("tree", "a.sps"),
+      ("guy", "sprites/guy.png"),
+      ("rock", "sprites/rock.png"),
+      ("tree", "b.json"),
+    ]
+    .iter()
+    .map(|(name, origin)| (name.to_string(), origin.to_string()))
+    .collect();
+    assert_eq!(check_names(&names[..2]), Ok(()));
+    assert_eq!(
+      check_names(&names).err().unwrap(),
+      "sprite \"guy\" is in both \"a.sps\" and \"sprites/guy.png\"\n\
+       sprite \"tree\" is in both \"a.sps\" and \"b.json\""
+    );
+  }
+
+  #[test]
+  fn names_images_after_their_files() {
+    assert_eq!(
+      image_sprite_name("media/sprites/guy.png"),
+      Ok("guy".to_string())
+    );
+    assert_eq!(
+      image_sprite_name("media/big guy.png").err().unwrap(),
+      "image \"media/big guy.png\" has a space in its name"
+    );
+  }
+}
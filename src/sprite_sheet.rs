@@ -7,32 +7,74 @@ use sdl2::render::TextureCreator;
 use sdl2::surface::Surface;
 use sdl2::video::WindowContext;
 
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::str::FromStr;
 
+use crate::aseprite::Export;
+
 pub struct SpriteSheet<'texture> {
+  // This sheet's index in the SpriteRegistry.
+  pub id: usize,
   pub texture: Texture<'texture>,
   pub sprite_map: Vec<SpriteRef>,
+  pub animations: Vec<Animation>,
+
+  // Lookup tables, built at load time.
+  sprite_index: HashMap<String, SpriteHandle>,
+  // For each sprite, by handle: its animations. See
+  // SpriteSheet::animation_for.
+  sprite_animations: Vec<SpriteAnimations>,
+}
+
+// A sprite's animations, as their suffixes and indexes in
+// SpriteSheet::animations.
+type SpriteAnimations = Vec<(String, usize)>;
+
+// Refers to a sprite in one of the SpriteRegistry's sheets. Looking up a
+// sprite by handle is a plain index, and a handle can only be had for a sprite
+// that exists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SpriteHandle {
+  pub sheet: usize,
+  index: usize,
 }
 
 impl<'texture> SpriteSheet<'texture> {
+  // Reads a sprite sheet from a .sps file, or from the .json file that
+  // Aseprite exports. See aseprite.rs for how the latter is read.
+  //
+  // The .sps format is like so:
+  // IMAGE_PATH
+  // N_SPRITES
+  // SPRITES
+  // N_ANIMATIONS
+  // ANIMATIONS
+  //
+  // IMAGE_PATH is relative to the .sps file. SPRITES is N_SPRITES lines, each
+  // parsed by SpriteRef::from_str, and ANIMATIONS is N_ANIMATIONS lines, each
+  // parsed by Animation::from_str. The animation section is optional. Sprite
+  // and animation names must be unique.
   pub fn from_file<'txc>(
+    id: usize,
     sprite_map_path: &str,
     texture_creator: &'txc TextureCreator<WindowContext>,
   ) -> Result<SpriteSheet<'txc>, String> {
-    let mut sprite_map = vec![];
     let file =
       std::fs::read_to_string(sprite_map_path).map_err(|e| format!("err reading file: {:?}", e))?;
+
+    if sprite_map_path.ends_with(".json") {
+      let export = Export::from_json(&file)?;
+      let texture = load_texture(sprite_map_path, &export.image_path, texture_creator)?;
+      return SpriteSheet::new(id, texture, export.sprites, export.animations);
+    }
+
+    let mut sprite_map: Vec<SpriteRef> = vec![];
     let mut lines = file.lines();
 
     // Read the image_path from the map file, then load it and create a texture.
     let image_path = lines.next().ok_or("sprite sheet missing img path")?;
-    let parent_dir = Path::new(&sprite_map_path).parent().ok_or(format!(
-      "sprite sheet file has no parent dir, so could not open the png file"
-    ))?;
-    let texture = Surface::from_file(parent_dir.join(image_path))?
-      .as_texture(texture_creator)
-      .map_err(|e| format!("err making texture: {:?}", e))?;
+    let texture = load_texture(sprite_map_path, image_path, texture_creator)?;
 
     // Read the sprite map from the map file.
     let n_sprites = u32::from_str_radix(lines.next().ok_or("sprite sheet missing n_sprites")?, 10)
@@ -42,62 +84,196 @@ impl<'texture> SpriteSheet<'texture> {
       sprite_map.push(line.parse()?);
     }
 
+    // Read the animations, if there are any.
+    let mut animations: Vec<Animation> = vec![];
+    if let Some(line) = lines.next() {
+      let n_animations: u32 = line
+        .parse()
+        .map_err(|e| format!("err parsing n_animations: {:?}", e))?;
+      for _ in 0..n_animations {
+        let line = lines.next().ok_or("sprite sheet has too few animations")?;
+        animations.push(line.parse()?);
+      }
+    }
+
+    SpriteSheet::new(id, texture, sprite_map, animations)
+  }
+
+  // Builds the lookup tables, and checks that names are unique and that
+  // animations only use sprites that exist.
+  pub fn new(
+    id: usize,
+    texture: Texture<'texture>,
+    sprite_map: Vec<SpriteRef>,
+    mut animations: Vec<Animation>,
+  ) -> Result<SpriteSheet<'texture>, String> {
+    let (sprite_index, sprite_animations) = build_index(id, &sprite_map, &mut animations)?;
     Ok(SpriteSheet {
+      id,
       texture,
       sprite_map,
+      animations,
+      sprite_index,
+      sprite_animations,
     })
   }
 
+  // Finds the named sprite, or returns an error if there's no such sprite.
+  pub fn sprite(&self, name: &str) -> Result<SpriteHandle, String> {
+    self
+      .sprite_index
+      .get(name)
+      .copied()
+      .ok_or(format!("sprite \"{}\" not found", name))
+  }
+
+  // Names of all the sprites in the sheet, with their handles.
+  pub fn sprites(&self) -> impl Iterator<Item = (&str, SpriteHandle)> {
+    self
+      .sprite_index
+      .iter()
+      .map(|(name, h)| (name.as_str(), *h))
+  }
+
+  // Returns the sprite's animation with the given suffix. For example, the
+  // sprite "foo" has the animation "foo_walk" for the suffix "walk".
+  //
+  // Animations live in the same sheet as their sprite.
+  pub fn animation_for(&self, sprite: SpriteHandle, suffix: &str) -> Option<&Animation> {
+    self.sprite_animations[sprite.index]
+      .iter()
+      .find(|(s, _)| s == suffix)
+      .map(|(_, i)| &self.animations[*i])
+  }
+
   pub fn blit_sprite_to_rect<Ctx: RenderTarget>(
     &self,
-    sprite_id: &str,
+    sprite: SpriteHandle,
     canvas: &mut Canvas<Ctx>,
     dst_rect: Rect,
   ) -> Result<(), String> {
-    for sprite_ref in self.sprite_map.iter() {
-      if sprite_ref.name == sprite_id {
-        let src_rect = sprite_ref.rect();
-        canvas.copy(&self.texture, src_rect, dst_rect)?;
-        return Ok(());
-      }
-    }
-    Err(format!("sprite \"{}\" not found", sprite_id))
+    let src_rect = self.sprite_map[sprite.index].rect();
+    canvas.copy(&self.texture, src_rect, dst_rect)
+  }
+
+  // Like blit_sprite_to_rect, but rotated clockwise by angle degrees around
+  // the center of dst_rect.
+  pub fn blit_sprite_to_rect_rotated<Ctx: RenderTarget>(
+    &self,
+    sprite: SpriteHandle,
+    canvas: &mut Canvas<Ctx>,
+    dst_rect: Rect,
+    angle: f64,
+  ) -> Result<(), String> {
+    let src_rect = self.sprite_map[sprite.index].rect();
+    canvas.copy_ex(&self.texture, src_rect, dst_rect, angle, None, false, false)
   }
 
   pub fn blit_sprite<Ctx: RenderTarget>(
     &self,
-    sprite_id: &str,
+    sprite: SpriteHandle,
     canvas: &mut Canvas<Ctx>,
     x_off: u32,
     y_off: u32,
     mag_factor: u32,
   ) -> Result<Rect, String> {
-    for sprite_ref in self.sprite_map.iter() {
-      if sprite_ref.name == sprite_id {
-        let src_rect = sprite_ref.rect();
-        let dst_rect = Rect::new(
-          x_off as i32,
-          y_off as i32,
-          mag_factor * src_rect.width(),
-          mag_factor * src_rect.height(),
-        );
-        canvas.copy(&self.texture, src_rect, dst_rect)?;
-        return Ok(dst_rect);
+    let src_rect = self.sprite_map[sprite.index].rect();
+    let dst_rect = Rect::new(
+      x_off as i32,
+      y_off as i32,
+      mag_factor * src_rect.width(),
+      mag_factor * src_rect.height(),
+    );
+    canvas.copy(&self.texture, src_rect, dst_rect)?;
+    Ok(dst_rect)
+  }
+}
+
+// The lookup tables for SpriteSheet::new: sprites by name, and each sprite's
+// animations by suffix. Resolves the animations' frames along the way.
+fn build_index(
+  id: usize,
+  sprite_map: &[SpriteRef],
+  animations: &mut [Animation],
+) -> Result<(HashMap<String, SpriteHandle>, Vec<SpriteAnimations>), String> {
+  let mut sprite_index = HashMap::new();
+  for (i, sprite_ref) in sprite_map.iter().enumerate() {
+    if sprite_index
+      .insert(
+        sprite_ref.name.clone(),
+        SpriteHandle {
+          sheet: id,
+          index: i,
+        },
+      )
+      .is_some()
+    {
+      return Err(format!("duplicate sprite \"{}\"", sprite_ref.name));
+    }
+  }
+
+  let mut sprite_animations = vec![vec![]; sprite_map.len()];
+  let mut anim_names = HashSet::new();
+  for (i, animation) in animations.iter_mut().enumerate() {
+    if !anim_names.insert(animation.name.clone()) {
+      return Err(format!("duplicate animation \"{}\"", animation.name));
+    }
+    for frame in animation.frames.iter_mut() {
+      frame.sprite.handle = Some(*sprite_index.get(&frame.sprite.name).ok_or(format!(
+        "animation \"{}\" uses unknown sprite \"{}\"",
+        animation.name, frame.sprite.name
+      ))?);
+    }
+    // An animation named "SPRITE_SUFFIX" belongs to the sprite SPRITE.
+    if let Some((sprite, suffix)) = animation.name.rsplit_once('_') {
+      if let Some(handle) = sprite_index.get(sprite) {
+        sprite_animations[handle.index].push((suffix.to_string(), i));
       }
     }
-    Err(format!("sprite \"{}\" not found", sprite_id))
   }
+
+  Ok((sprite_index, sprite_animations))
+}
+
+// Loads the image at image_path, relative to the sprite sheet file at
+// sheet_path.
+fn load_texture<'txc>(
+  sheet_path: &str,
+  image_path: &str,
+  texture_creator: &'txc TextureCreator<WindowContext>,
+) -> Result<Texture<'txc>, String> {
+  let parent_dir = Path::new(sheet_path).parent().ok_or(format!(
+    "sprite sheet file has no parent dir, so could not open the png file"
+  ))?;
+  Surface::from_file(parent_dir.join(image_path))?
+    .as_texture(texture_creator)
+    .map_err(|e| format!("err making texture: {:?}", e))
 }
 
 pub struct SpriteRef {
-  pub name: SpriteKey,
+  pub name: String,
   pub offset_x: u32,
   pub offset_y: u32,
   pub width: u32,
   pub height: u32,
 }
 
-pub type SpriteKey = String; // Must not have spaces.
+// The name of a sprite, along with its handle once it's been resolved against
+// a sprite sheet. Names must not have spaces.
+#[derive(Clone, Debug)]
+pub struct SpriteKey {
+  pub name: String,
+  pub handle: Option<SpriteHandle>,
+}
+
+impl SpriteKey {
+  pub fn new(name: &str) -> SpriteKey {
+    SpriteKey {
+      name: name.to_string(),
+      handle: None,
+    }
+  }
+}
 
 impl SpriteRef {
   fn rect(&self) -> Rect {
@@ -129,3 +305,203 @@ impl FromStr for SpriteRef {
     })
   }
 }
+
+pub struct Animation {
+  pub name: String,
+  pub frames: Vec<AnimationFrame>,
+  // Looping animations start over after the last frame. The others stay on
+  // their last frame.
+  pub looping: bool,
+}
+
+pub struct AnimationFrame {
+  pub sprite: SpriteKey,
+  pub duration_ms: u32,
+}
+
+impl Animation {
+  // Returns the sprite to show elapsed_ms into the animation.
+  pub fn frame_at(&self, elapsed_ms: u32) -> SpriteHandle {
+    let total_ms: u32 = self.frames.iter().map(|f| f.duration_ms).sum();
+    let mut t = if self.looping && total_ms > 0 {
+      elapsed_ms % total_ms
+    } else {
+      elapsed_ms
+    };
+    for frame in self.frames.iter() {
+      if t < frame.duration_ms {
+        return frame.handle();
+      }
+      t -= frame.duration_ms;
+    }
+    // Past the end of a one-shot animation.
+    self.frames[self.frames.len() - 1].handle()
+  }
+}
+
+impl AnimationFrame {
+  fn handle(&self) -> SpriteHandle {
+    // Frames are all resolved when the sprite sheet is loaded.
+    self.sprite.handle.unwrap()
+  }
+}
+
+impl FromStr for Animation {
+  type Err = String;
+
+  // Parses a string of the form "NAME LOOP|ONCE FRAME:MS FRAME:MS ..."
+  // into an Animation. Each FRAME is the name of a sprite, shown for MS
+  // milliseconds. There must be at least one frame.
+  fn from_str(line: &str) -> Result<Self, Self::Err> {
+    let elts: Vec<_> = line.split(' ').collect();
+    if elts.len() < 3 {
+      return Err(format!("animation line \"{}\" has too few elements", line));
+    }
+    let looping = match elts[1] {
+      "LOOP" => true,
+      "ONCE" => false,
+      other => return Err(format!("expected LOOP or ONCE, got \"{}\"", other)),
+    };
+    let mut frames = vec![];
+    for elt in elts[2..].iter() {
+      let (sprite, duration) = elt
+        .split_once(':')
+        .ok_or(format!("animation frame \"{}\" should be FRAME:MS", elt))?;
+      frames.push(AnimationFrame {
+        sprite: SpriteKey::new(sprite),
+        duration_ms: u32::from_str(duration).map_err(|e| format!("{:?}", e))?,
+      });
+    }
+    Ok(Animation {
+      name: elts[0].to_string(),
+      frames,
+      looping,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sprite(name: &str) -> SpriteRef {
+    SpriteRef {
+      name: name.to_string(),
+      offset_x: 0,
+      offset_y: 0,
+      width: 1,
+      height: 1,
+    }
+  }
+
+  // Parses the animation and resolves it against the sprites a, b and c, at
+  // indexes 0, 1 and 2.
+  fn animation(line: &str) -> Animation {
+    let mut animations = vec![line.parse().unwrap()];
+    build_index(0, &[sprite("a"), sprite("b"), sprite("c")], &mut animations).unwrap();
+    animations.pop().unwrap()
+  }
+
+  fn index_at(animation: &Animation, elapsed_ms: u32) -> usize {
+    animation.frame_at(elapsed_ms).index
+  }
+
+  #[test]
+  fn looping_animations_start_over() {
+    let anim = animation("spin LOOP a:100 b:50 c:10");
+    let frames: Vec<_> = [0, 99, 100, 149, 150, 159, 160, 259, 260, 310]
+      .iter()
+      .map(|&t| index_at(&anim, t))
+      .collect();
+    assert_eq!(frames, [0, 0, 1, 1, 2, 2, 0, 0, 1, 2]);
+  }
+
+  #[test]
+  fn one_shot_animations_stay_on_their_last_frame() {
+    let anim = animation("die ONCE a:100 b:50");
+    assert_eq!(index_at(&anim, 99), 0);
+    assert_eq!(index_at(&anim, 100), 1);
+    assert_eq!(index_at(&anim, 150), 1);
+    assert_eq!(index_at(&anim, 100_000), 1);
+  }
+
+  #[test]
+  fn parses_animations() {
+    let anim: Animation = "a_walk LOOP a:100 b:20".parse().unwrap();
+    assert_eq!(anim.name, "a_walk");
+    assert!(anim.looping);
+    let frames: Vec<_> = anim
+      .frames
+      .iter()
+      .map(|f| (f.sprite.name.as_str(), f.duration_ms))
+      .collect();
+    assert_eq!(frames, [("a", 100), ("b", 20)]);
+    assert!(!"a ONCE a:1".parse::<Animation>().unwrap().looping);
+
+    let err = |line: &str| line.parse::<Animation>().err().unwrap();
+    assert_eq!(
+      err("a LOOP"),
+      "animation line \"a LOOP\" has too few elements"
+    );
+    assert_eq!(
+      err("a FOREVER a:1"),
+      "expected LOOP or ONCE, got \"FOREVER\""
+    );
+    assert_eq!(err("a LOOP a"), "animation frame \"a\" should be FRAME:MS");
+    assert!("a LOOP a:-1".parse::<Animation>().is_err());
+  }
+
+  #[test]
+  fn parses_sprite_refs() {
+    let sprite: SpriteRef = "guy 1 2 30 40".parse().unwrap();
+    assert_eq!(sprite.name, "guy");
+    assert_eq!(
+      (
+        sprite.offset_x,
+        sprite.offset_y,
+        sprite.width,
+        sprite.height
+      ),
+      (1, 2, 30, 40)
+    );
+    assert!("guy 1 2 30".parse::<SpriteRef>().is_err());
+    assert!("two words 1 2 30 40".parse::<SpriteRef>().is_err());
+    assert!("guy 1 2 30 -40".parse::<SpriteRef>().is_err());
+  }
+
+  #[test]
+  fn rejects_duplicate_and_unknown_names() {
+    let err = |sprites: &[SpriteRef], animations: &[&str]| {
+      let mut animations: Vec<Animation> = animations.iter().map(|a| a.parse().unwrap()).collect();
+      build_index(0, sprites, &mut animations).err().unwrap()
+    };
+    assert_eq!(
+      err(&[sprite("a"), sprite("a")], &[]),
+      "duplicate sprite \"a\""
+    );
+    assert_eq!(
+      err(&[sprite("a")], &["x LOOP a:1", "x ONCE a:1"]),
+      "duplicate animation \"x\""
+    );
+    assert_eq!(
+      err(&[sprite("a")], &["x LOOP b:1"]),
+      "animation \"x\" uses unknown sprite \"b\""
+    );
+  }
+
+  #[test]
+  fn finds_animations_by_suffix() {
+    let mut animations: Vec<Animation> = ["a_walk LOOP a:1", "b_walk LOOP b:1", "a_die ONCE b:1"]
+      .iter()
+      .map(|a| a.parse().unwrap())
+      .collect();
+    let (index, sprite_animations) =
+      build_index(3, &[sprite("a"), sprite("b")], &mut animations).unwrap();
+    let a = index["a"];
+    assert_eq!(a.sheet, 3);
+    assert_eq!(
+      sprite_animations[a.index],
+      [("walk".to_string(), 0), ("die".to_string(), 2)]
+    );
+  }
+}
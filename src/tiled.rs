@@ -0,0 +1,806 @@
+// Imports maps made with the Tiled editor (https://www.mapeditor.org), from
+// either its XML (.tmx) or JSON (.tmj) format.
+//
+// Tiles: each tile in a tileset says which GridTile it is with a custom
+// property "tile", set to a name that GridTile::from_str understands (eg.
+// "wall"), or failing that, with its class. Empty cells are floor. Later tile
+// layers cover earlier ones.
+//
+// Objects: in any object layer, an object's class says what it is:
+// - "player_spawn" is a player spawn. Players get them in the order they're
+//   listed.
+// - "spawner" is an enemy spawner, with its kind in the "kind" property.
+// - "pickup" is a pickup, with its kind in the "kind" property.
+// - "region" is a region named after the object, covering every tile its
+//   rectangle touches.
+// Points are placed in the tile they're in, and other objects in the tile under
+// their center. Objects with any other class are ignored.
+//
+// Custom properties on the map, and on spawners, pickups and regions, are kept
+// in their properties.
+//
+// Only what we need is supported: orthogonal, finite maps, with tilesets
+// embedded in the map and tile layers stored as CSV.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::map::{
+  GridTile, Map, MapEntity, PickupSpawn, Properties, Region, Spawner, TilePoint, MAX_MAP_SIDE,
+};
+
+// The top bits of a tile id say how the tile is flipped, which doesn't matter
+// to us.
+const GID_MASK: u32 = 0x0fff_ffff;
+
+pub fn from_json(json: &str) -> Result<Map, String> {
+  let map: json::Map =
+    serde_json::from_str(json).map_err(|e| format!("err parsing tiled json: {}", e))?;
+  map.into_tiled()?.into_map()
+}
+
+pub fn from_tmx(tmx: &str) -> Result<Map, String> {
+  let root = Element::parse(tmx)?;
+  TiledMap::from_xml(&root)?.into_map()
+}
+
+// The parts of a Tiled map that we use, read from either format.
+struct TiledMap {
+  orientation: String,
+  infinite: bool,
+  width: u32,
+  height: u32,
+  tile_width: u32,
+  tile_height: u32,
+  properties: Properties,
+  tilesets: Vec<Tileset>,
+  // Group layers are flattened into this, in drawing order.
+  layers: Vec<Layer>,
+}
+
+struct Tileset {
+  first_gid: u32,
+  // Only tiles with a class or properties are listed, by their id within the
+  // tileset.
+  tiles: HashMap<u32, Tile>,
+}
+
+struct Tile {
+  class: String,
+  properties: Properties,
+}
+
+enum Layer {
+  // One tile id per cell, row by row. 0 is no tile.
+  Tiles(Vec<u32>),
+  Objects(Vec<Object>),
+}
+
+struct Object {
+  name: String,
+  class: String,
+  // In pixels.
+  x: f32,
+  y: f32,
+  width: f32,
+  height: f32,
+  point: bool,
+  properties: Properties,
+}
+
+impl TiledMap {
+  fn into_map(self) -> Result<Map, String> {
+    if self.orientation != "orthogonal" {
+      return Err(format!(
+        "only orthogonal maps are supported, not {}",
+        self.orientation
+      ));
+    }
+    if self.infinite {
+      return Err("infinite maps aren't supported".to_string());
+    }
+    if self.tile_width == 0 || self.tile_height == 0 {
+      return Err("tiles have no size".to_string());
+    }
+    if self.width == 0 || self.height == 0 {
+      return Err("map has no size".to_string());
+    }
+    if self.width > MAX_MAP_SIDE || self.height > MAX_MAP_SIDE {
+      return Err(format!(
+        "map is {}x{} tiles, but can be at most {}x{}",
+        self.width, self.height, MAX_MAP_SIDE, MAX_MAP_SIDE
+      ));
+    }
+
+    let n_cells = self.width as usize * self.height as usize;
+    let mut grid_tiles = vec![GridTile::Empty; n_cells];
+    for layer in self.layers.iter() {
+      let gids = match layer {
+        Layer::Tiles(gids) => gids,
+        Layer::Objects(_) => continue,
+      };
+      if gids.len() != n_cells {
+        return Err(format!(
+          "tile layer has {} tiles, but the map has {}",
+          gids.len(),
+          n_cells
+        ));
+      }
+      for (cell, gid) in grid_tiles.iter_mut().zip(gids) {
+        let gid = gid & GID_MASK;
+        if gid != 0 {
+          *cell = self.grid_tile(gid)?;
+        }
+      }
+    }
+
+    let mut map = Map::new(self.width, self.height, grid_tiles);
+    map.properties = self.properties.clone();
+    for layer in self.layers.iter() {
+      let objects = match layer {
+        Layer::Objects(objects) => objects,
+        Layer::Tiles(_) => continue,
+      };
+      for object in objects.iter() {
+        let entity = match self.entity(object)? {
+          Some(entity) => entity,
+          None => continue,
+        };
+        map
+          .check_entity(&entity)
+          .map_err(|e| format!("object \"{}\": {}", object.name, e))?;
+        match entity {
+          MapEntity::PlayerSpawn(p) => map.player_spawns.push(p),
+          MapEntity::Spawner(s) => map.spawners.push(s),
+          MapEntity::Pickup(p) => map.pickups.push(p),
+          MapEntity::Region(r) => map.regions.push(r),
+        }
+      }
+    }
+    Ok(map)
+  }
+
+  fn grid_tile(&self, gid: u32) -> Result<GridTile, String> {
+    // The tile belongs to the tileset with the last first_gid before it.
+    let tileset = self
+      .tilesets
+      .iter()
+      .filter(|t| t.first_gid <= gid)
+      .max_by_key(|t| t.first_gid)
+      .ok_or(format!("tile {} isn't in any tileset", gid))?;
+    let name = tileset.tiles.get(&(gid - tileset.first_gid)).and_then(|t| {
+      t.properties
+        .get("tile")
+        .or(Some(&t.class).filter(|c| !c.is_empty()))
+    });
+    match name {
+      Some(name) => name.parse().map_err(|e| format!("tile {}: {}", gid, e)),
+      None => Err(format!(
+        "tile {} has no \"tile\" property or class to say what it is",
+        gid
+      )),
+    }
+  }
+
+  // Returns the entity for object, or None if it isn't one we know.
+  fn entity(&self, object: &Object) -> Result<Option<MapEntity>, String> {
+    let (tw, th) = (self.tile_width as f32, self.tile_height as f32);
+    let tile_at = |x: f32, y: f32| {
+      if x < 0. || y < 0. {
+        return Err(format!("object \"{}\" is off the map", object.name));
+      }
+      Ok(TilePoint::new((x / tw) as u32, (y / th) as u32))
+    };
+    let pos = if object.point {
+      tile_at(object.x, object.y)?
+    } else {
+      tile_at(object.x + object.width / 2., object.y + object.height / 2.)?
+    };
+    let kind = || {
+      object.properties.get("kind").ok_or(format!(
+        "object \"{}\" has no \"kind\" property",
+        object.name
+      ))
+    };
+
+    let entity = match object.class.as_str() {
+      "player_spawn" => MapEntity::PlayerSpawn(pos),
+      "spawner" => MapEntity::Spawner(Spawner {
+        kind: kind()?.clone(),
+        pos,
+        properties: object.properties.clone(),
+      }),
+      "pickup" => MapEntity::Pickup(PickupSpawn {
+        kind: kind()?.parse()?,
+        pos,
+        properties: object.properties.clone(),
+      }),
+      "region" => {
+        let top_left = tile_at(object.x, object.y)?;
+        // Every tile the rectangle touches, so a region is never empty.
+        let (left, top) = ((object.x / tw) as u32, (object.y / th) as u32);
+        let right = ((object.x + object.width) / tw).ceil() as u32;
+        let bottom = ((object.y + object.height) / th).ceil() as u32;
+        MapEntity::Region(Region {
+          name: object.name.clone(),
+          top_left,
+          width: u32::max(right, left + 1) - left,
+          height: u32::max(bottom, top + 1) - top,
+          properties: object.properties.clone(),
+        })
+      }
+      _ => return Ok(None),
+    };
+    Ok(Some(entity))
+  }
+}
+
+// Tiled's JSON format, as far as we read it.
+mod json {
+  use serde::Deserialize;
+  use serde_json::Value;
+
+  use super::{Layer, Object, Tile, TiledMap, Tileset};
+  use crate::map::Properties;
+
+  #[derive(Deserialize)]
+  pub struct Map {
+    #[serde(default)]
+    orientation: String,
+    #[serde(default)]
+    infinite: bool,
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    properties: Vec<Property>,
+    #[serde(default)]
+    tilesets: Vec<TilesetJson>,
+    layers: Vec<LayerJson>,
+  }
+
+  #[derive(Deserialize)]
+  struct Property {
+    name: String,
+    value: Value,
+  }
+
+  #[derive(Deserialize)]
+  struct TilesetJson {
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(default)]
+    tiles: Vec<TileJson>,
+  }
+
+  // Tiled has called a tile's class both "type" and "class" over the years.
+  #[derive(Deserialize)]
+  struct TileJson {
+    id: u32,
+    #[serde(default, rename = "type")]
+    type_: String,
+    #[serde(default)]
+    class: String,
+    #[serde(default)]
+    properties: Vec<Property>,
+  }
+
+  #[derive(Deserialize)]
+  struct LayerJson {
+    #[serde(rename = "type")]
+    type_: String,
+    #[serde(default)]
+    name: String,
+    data: Option<Value>,
+    #[serde(default)]
+    objects: Vec<ObjectJson>,
+    #[serde(default)]
+    layers: Vec<LayerJson>,
+  }
+
+  #[derive(Deserialize)]
+  struct ObjectJson {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    type_: String,
+    #[serde(default)]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    point: bool,
+    #[serde(default)]
+    properties: Vec<Property>,
+  }
+
+  impl Map {
+    pub fn into_tiled(self) -> Result<TiledMap, String> {
+      let mut tilesets = vec![];
+      for tileset in self.tilesets {
+        if let Some(source) = tileset.source {
+          return Err(format!(
+            "tileset \"{}\" isn't embedded in the map; embed it with Map > Embed Tilesets",
+            source
+          ));
+        }
+        let tiles = tileset
+          .tiles
+          .into_iter()
+          .map(|t| {
+            let class = if t.class.is_empty() { t.type_ } else { t.class };
+            let properties = properties(t.properties);
+            (t.id, Tile { class, properties })
+          })
+          .collect();
+        tilesets.push(Tileset {
+          first_gid: tileset.firstgid,
+          tiles,
+        });
+      }
+
+      let mut layers = vec![];
+      flatten_layers(self.layers, &mut layers)?;
+
+      Ok(TiledMap {
+        orientation: self.orientation,
+        infinite: self.infinite,
+        width: self.width,
+        height: self.height,
+        tile_width: self.tilewidth,
+        tile_height: self.tileheight,
+        properties: properties(self.properties),
+        tilesets,
+        layers,
+      })
+    }
+  }
+
+  fn flatten_layers(layers: Vec<LayerJson>, out: &mut Vec<Layer>) -> Result<(), String> {
+    for layer in layers {
+      match layer.type_.as_str() {
+        "tilelayer" => {
+          let data = match layer.data {
+            Some(Value::Array(data)) => data,
+            _ => {
+              return Err(format!(
+                "tile layer \"{}\" isn't stored as CSV; set its Tile Layer Format to CSV",
+                layer.name
+              ))
+            }
+          };
+          let gids = data
+            .iter()
+            .map(|v| {
+              v.as_u64().map(|gid| gid as u32).ok_or(format!(
+                "tile layer \"{}\" has a bad tile {}",
+                layer.name, v
+              ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+          out.push(Layer::Tiles(gids));
+        }
+        "objectgroup" => {
+          let objects = layer
+            .objects
+            .into_iter()
+            .map(|o| Object {
+              name: o.name,
+              class: if o.class.is_empty() { o.type_ } else { o.class },
+              x: o.x,
+              y: o.y,
+              width: o.width,
+              height: o.height,
+              point: o.point,
+              properties: properties(o.properties),
+            })
+            .collect();
+          out.push(Layer::Objects(objects));
+        }
+        "group" => flatten_layers(layer.layers, out)?,
+        // Image layers are just decoration.
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  fn properties(props: Vec<Property>) -> Properties {
+    props
+      .into_iter()
+      .map(|p| {
+        let value = match p.value {
+          Value::String(s) => s,
+          other => other.to_string(),
+        };
+        (p.name, value)
+      })
+      .collect()
+  }
+}
+
+impl TiledMap {
+  fn from_xml(root: &Element) -> Result<TiledMap, String> {
+    if root.name != "map" {
+      return Err(format!("expected <map>, found <{}>", root.name));
+    }
+
+    let mut tilesets = vec![];
+    for tileset in root.children_named("tileset") {
+      if let Some(source) = tileset.attrs.get("source") {
+        return Err(format!(
+          "tileset \"{}\" isn't embedded in the map; embed it with Map > Embed Tilesets",
+          source
+        ));
+      }
+      let mut tiles = HashMap::new();
+      for tile in tileset.children_named("tile") {
+        let class = tile
+          .attrs
+          .get("class")
+          .or(tile.attrs.get("type"))
+          .cloned()
+          .unwrap_or_default();
+        tiles.insert(
+          tile.num_attr("id")?,
+          Tile {
+            class,
+            properties: xml_properties(tile),
+          },
+        );
+      }
+      tilesets.push(Tileset {
+        first_gid: tileset.num_attr("firstgid")?,
+        tiles,
+      });
+    }
+
+    let mut layers = vec![];
+    flatten_xml_layers(root, &mut layers)?;
+
+    Ok(TiledMap {
+      orientation: root.attrs.get("orientation").cloned().unwrap_or_default(),
+      infinite: root.attrs.get("infinite").is_some_and(|i| i == "1"),
+      width: root.num_attr("width")?,
+      height: root.num_attr("height")?,
+      tile_width: root.num_attr("tilewidth")?,
+      tile_height: root.num_attr("tileheight")?,
+      properties: xml_properties(root),
+      tilesets,
+      layers,
+    })
+  }
+}
+
+fn flatten_xml_layers(parent: &Element, out: &mut Vec<Layer>) -> Result<(), String> {
+  for layer in parent.children.iter() {
+    match layer.name.as_str() {
+      "layer" => {
+        let name = layer.attrs.get("name").cloned().unwrap_or_default();
+        let data = layer
+          .children_named("data")
+          .next()
+          .ok_or(format!("tile layer \"{}\" has no data", name))?;
+        let gids = match data.attrs.get("encoding").map(|e| e.as_str()) {
+          Some("csv") => data
+            .text
+            .split(',')
+            .map(|gid| u32::from_str(gid.trim()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("tile layer \"{}\" has a bad tile: {:?}", name, e))?,
+          None => data
+            .children_named("tile")
+            .map(|tile| match tile.attrs.get("gid") {
+              Some(_) => tile.num_attr("gid"),
+              None => Ok(0),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+          Some(_) => {
+            return Err(format!(
+              "tile layer \"{}\" isn't stored as CSV; set its Tile Layer Format to CSV",
+              name
+            ))
+          }
+        };
+        out.push(Layer::Tiles(gids));
+      }
+      "objectgroup" => {
+        let mut objects = vec![];
+        for object in layer.children_named("object") {
+          let class = object
+            .attrs
+            .get("class")
+            .or(object.attrs.get("type"))
+            .cloned()
+            .unwrap_or_default();
+          let opt_num = |name: &str| match object.attrs.get(name) {
+            Some(_) => object.num_attr(name),
+            None => Ok(0.),
+          };
+          objects.push(Object {
+            name: object.attrs.get("name").cloned().unwrap_or_default(),
+            class,
+            x: object.num_attr("x")?,
+            y: object.num_attr("y")?,
+            width: opt_num("width")?,
+            height: opt_num("height")?,
+            point: object.children_named("point").next().is_some(),
+            properties: xml_properties(object),
+          });
+        }
+        out.push(Layer::Objects(objects));
+      }
+      "group" => flatten_xml_layers(layer, out)?,
+      _ => {}
+    }
+  }
+  Ok(())
+}
+
+// Reads the <properties> of element. Long text values are stored as the
+// property's text rather than in its value attribute.
+fn xml_properties(element: &Element) -> Properties {
+  let mut properties = Properties::new();
+  for props in element.children_named("properties") {
+    for prop in props.children_named("property") {
+      if let Some(name) = prop.attrs.get("name") {
+        let value = prop
+          .attrs
+          .get("value")
+          .cloned()
+          .unwrap_or_else(|| prop.text.clone());
+        properties.insert(name.clone(), value);
+      }
+    }
+  }
+  properties
+}
+
+// Just enough of an XML document tree to walk a .tmx file.
+struct Element {
+  name: String,
+  attrs: HashMap<String, String>,
+  children: Vec<Element>,
+  text: String,
+}
+
+impl Element {
+  // Reads a whole document, returning its root element.
+  fn parse(xml: &str) -> Result<Element, String> {
+    // The root is kept at the bottom of the stack, under whichever elements
+    // are open.
+    let mut stack: Vec<Element> = vec![];
+    for event in EventReader::from_str(xml) {
+      match event.map_err(|e| format!("err parsing tmx: {}", e))? {
+        XmlEvent::StartElement {
+          name, attributes, ..
+        } => stack.push(Element {
+          name: name.local_name,
+          attrs: attributes
+            .into_iter()
+            .map(|a| (a.name.local_name, a.value))
+            .collect(),
+          children: vec![],
+          text: String::new(),
+        }),
+        XmlEvent::EndElement { .. } => {
+          let element = stack.pop().ok_or("unbalanced tmx")?;
+          match stack.last_mut() {
+            Some(parent) => parent.children.push(element),
+            None => return Ok(element),
+          }
+        }
+        XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+          if let Some(element) = stack.last_mut() {
+            element.text.push_str(&text);
+          }
+        }
+        _ => {}
+      }
+    }
+    Err("tmx has no root element".to_string())
+  }
+
+  fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+    self.children.iter().filter(move |c| c.name == name)
+  }
+
+  fn num_attr<T: FromStr>(&self, name: &str) -> Result<T, String> {
+    let value = self
+      .attrs
+      .get(name)
+      .ok_or(format!("<{}> has no {}", self.name, name))?;
+    value
+      .parse()
+      .map_err(|_| format!("<{}> has bad {} \"{}\"", self.name, name, value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::map::PickupKind;
+
+  // A 4x3 map with a wall tile found by property, floor and mud tiles found
+  // by class, and one of each kind of object, in both formats.
+  const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" renderorder="right-down" width="4" height="3" tilewidth="32" tileheight="32" infinite="0">
+ <properties>
+  <property name="music" value="cave.ogg"/>
+ </properties>
+ <tileset firstgid="1" name="tiles" tilewidth="32" tileheight="32" tilecount="4" columns="4">
+  <tile id="0" class="floor"/>
+  <tile id="1">
+   <properties>
+    <property name="tile" value="wall"/>
+   </properties>
+  </tile>
+  <tile id="2" class="mud"/>
+ </tileset>
+ <group name="terrain">
+  <layer id="1" name="ground" width="4" height="3">
+   <data encoding="csv">
+1,1,1,1,
+1,2,3,1,
+1,1,1,2
+</data>
+  </layer>
+ </group>
+ <objectgroup id="2" name="things">
+  <object id="1" name="p1" class="player_spawn" x="8" y="40">
+   <point/>
+  </object>
+  <object id="2" name="gob" class="spawner" x="64" y="64" width="32" height="32">
+   <properties>
+    <property name="kind" value="grunt"/>
+    <property name="count" type="int" value="3"/>
+   </properties>
+  </object>
+  <object id="3" name="medkit" class="pickup" x="64" y="32" width="16" height="16">
+   <properties>
+    <property name="kind" value="health"/>
+   </properties>
+  </object>
+  <object id="4" name="hall" class="region" x="0" y="32" width="70" height="20"/>
+  <object id="5" name="tree" class="decoration" x="0" y="0"/>
+ </objectgroup>
+</map>
+"#;
+
+  const JSON: &str = r#"{
+    "orientation": "orthogonal", "infinite": false,
+    "width": 4, "height": 3, "tilewidth": 32, "tileheight": 32,
+    "properties": [{"name": "music", "type": "string", "value": "cave.ogg"}],
+    "tilesets": [{
+      "firstgid": 1, "name": "tiles",
+      "tiles": [
+        {"id": 0, "type": "floor"},
+        {"id": 1, "properties": [{"name": "tile", "type": "string", "value": "wall"}]},
+        {"id": 2, "type": "mud"}
+      ]
+    }],
+    "layers": [
+      {"type": "group", "name": "terrain", "layers": [
+        {"type": "tilelayer", "name": "ground", "width": 4, "height": 3,
+         "data": [1, 1, 1, 1, 1, 2, 3, 1, 1, 1, 1, 2]}
+      ]},
+      {"type": "objectgroup", "name": "things", "objects": [
+        {"id": 1, "name": "p1", "type": "player_spawn", "x": 8, "y": 40, "point": true},
+        {"id": 2, "name": "gob", "type": "spawner", "x": 64, "y": 64, "width": 32, "height": 32,
+         "properties": [
+           {"name": "kind", "type": "string", "value": "grunt"},
+           {"name": "count", "type": "int", "value": 3}
+         ]},
+        {"id": 3, "name": "medkit", "type": "pickup", "x": 64, "y": 32, "width": 16, "height": 16,
+         "properties": [{"name": "kind", "type": "string", "value": "health"}]},
+        {"id": 4, "name": "hall", "type": "region", "x": 0, "y": 32, "width": 70, "height": 20},
+        {"id": 5, "name": "tree", "type": "decoration", "x": 0, "y": 0}
+      ]}
+    ]
+  }"#;
+
+  fn check(map: &Map) {
+    let tiles: Vec<_> = map.grid_tiles.iter().map(|t| t.name()).collect();
+    assert_eq!(
+      tiles,
+      vec![
+        "floor", "floor", "floor", "floor", //
+        "floor", "wall", "mud", "floor", //
+        "floor", "floor", "floor", "wall",
+      ]
+    );
+    assert_eq!(map.properties.get("music").unwrap(), "cave.ogg");
+    assert_eq!(map.player_spawns, vec![TilePoint::new(0, 1)]);
+    assert_eq!(map.spawners.len(), 1);
+    assert_eq!(map.spawners[0].kind, "grunt");
+    assert_eq!(map.spawners[0].pos, TilePoint::new(2, 2));
+    assert_eq!(map.spawners[0].properties.get("count").unwrap(), "3");
+    assert_eq!(map.pickups.len(), 1);
+    assert!(matches!(map.pickups[0].kind, PickupKind::Health));
+    assert_eq!(map.pickups[0].pos, TilePoint::new(2, 1));
+    assert_eq!(map.regions.len(), 1);
+    let hall = map.region("hall").unwrap();
+    assert_eq!(hall.top_left, TilePoint::new(0, 1));
+    assert_eq!((hall.width, hall.height), (3, 1));
+  }
+
+  #[test]
+  fn imports_tmx() {
+    check(&from_tmx(TMX).unwrap());
+  }
+
+  #[test]
+  fn imports_json() {
+    check(&from_json(JSON).unwrap());
+  }
+
+  #[test]
+  fn rejects_unmapped_tile() {
+    let tmx = TMX.replace("3,1,", "4,1,");
+    assert!(from_tmx(&tmx).err().unwrap().contains("tile 4"));
+  }
+
+  #[test]
+  fn rejects_external_tileset() {
+    let tmx = TMX.replace(
+      r#"name="tiles" tilewidth"#,
+      r#"source="tiles.tsx" tilewidth"#,
+    );
+    assert!(from_tmx(&tmx).err().unwrap().contains("Embed Tilesets"));
+  }
+
+  // Tiled leaves object names empty by default, and allows spaces, but
+  // neither can be saved in our own format.
+  #[test]
+  fn rejects_names_that_cant_be_saved() {
+    let tmx = TMX.replace(r#"name="hall""#, r#"name="great hall""#);
+    assert!(from_tmx(&tmx)
+      .err()
+      .unwrap()
+      .contains("region name \"great hall\" must not be empty or have spaces"));
+
+    let json = JSON.replace(r#""name": "hall""#, r#""name": """#);
+    assert!(from_json(&json).err().unwrap().contains("region name \"\""));
+
+    let tmx = TMX.replace(r#"value="grunt""#, r#"value="big grunt""#);
+    assert!(from_tmx(&tmx).err().unwrap().contains("spawner kind"));
+  }
+
+  #[test]
+  fn rejects_empty_map() {
+    let json = JSON.replace(
+      r#""width": 4, "height": 3, "tilewidth""#,
+      r#""width": 0, "height": 3, "tilewidth""#,
+    );
+    assert!(from_json(&json).err().unwrap().contains("no size"));
+  }
+
+  #[test]
+  fn rejects_huge_map() {
+    // Caught before anything is allocated for the tiles.
+    let json = JSON.replace(
+      r#""width": 4, "height": 3, "tilewidth""#,
+      r#""width": 100000, "height": 100000, "tilewidth""#,
+    );
+    assert_eq!(
+      from_json(&json).err().unwrap(),
+      format!(
+        "map is 100000x100000 tiles, but can be at most {}x{}",
+        MAX_MAP_SIDE, MAX_MAP_SIDE
+      )
+    );
+  }
+
+  #[test]
+  fn rejects_entity_on_wall() {
+    let json = JSON.replace(r#""x": 8, "y": 40"#, r#""x": 40, "y": 40"#);
+    assert!(from_json(&json).err().unwrap().contains("p1"));
+  }
+}